@@ -1,7 +1,3 @@
-const RETURN_VALUE_BUF: &str = "syscall_ret_val";
-const RETURN_VALUE_SIZE: usize = 4;
-const RETURN_VALUE_BUF_ALLIGNMENT: usize = 4;
-
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Register {
@@ -23,6 +19,7 @@ pub enum Register {
     PC,
 }
 
+#[allow(dead_code)]
 impl Register {
     pub fn as_str(&self) -> &'static str {
         match self {
@@ -46,42 +43,206 @@ impl Register {
     }
 }
 
+#[allow(dead_code)]
 pub fn syscall_3args(syscall_number: u32, arg0: &str, arg1: &str, arg2: &str) -> String {
     format!(
-        "\t{}\n\t{}\n\t{}\n\t{}\n\tsvc #0\n",
-        format!("mov r7, #{}", syscall_number),
+        "\tmov r7, #{}\n\t{}\n\t{}\n\t{}\n\tsvc #0\n",
+        syscall_number,
         into_register_load(arg0, "r0"),
         into_register_load(arg1, "r1"),
         into_register_load(arg2, "r2")
     )
 }
 
+#[allow(dead_code)]
 pub fn syscall_2args(syscall_number: u32, arg0: &str, arg1: &str) -> String {
     format!(
-        "\t{}\n\t{}\n\t{}\n\tsvc #0\n",
-        format!("mov r7, #{}", syscall_number),
+        "\tmov r7, #{}\n\t{}\n\t{}\n\tsvc #0\n",
+        syscall_number,
         into_register_load(arg0, "r0"),
         into_register_load(arg1, "r1")
     )
 }
 
+#[allow(dead_code)]
 pub fn syscall_1arg(syscall_number: u32, arg0: &str) -> String {
     format!(
-        "\t{}\n\t{}\n\tsvc #0\n",
-        format!("mov r7, #{}", syscall_number),
+        "\tmov r7, #{}\n\t{}\n\tsvc #0\n",
+        syscall_number,
         into_register_load(arg0, "r0")
     )
 }
 
 
 fn into_register_load(value: &str, register: &str) -> String {
-    if value.chars().all(|c| c.is_ascii_digit()) {
-        format!("mov {}, #{}", register, value)
+    if let Ok(n) = value.parse::<i32>() {
+        load_imm(register, n).trim_start().to_string()
     } else {
         format!("ldr {}, ={}", register, value)
     }
 }
 
+/// Loads any 32-bit constant; values that don't fit a `mov` immediate go
+/// through the literal pool.
+pub fn load_imm(reg: &str, value: i32) -> String {
+    if (0..=255).contains(&value) {
+        format!("\tmov {}, #{}", reg, value)
+    } else {
+        format!("\tldr {}, ={}", reg, value)
+    }
+}
+
+/// Whether `value` fits an ARM data-processing immediate: an 8-bit value
+/// rotated right by an even amount.
+pub fn is_immediate(value: u32) -> bool {
+    (0..16).any(|rotation| value.rotate_left(rotation * 2) <= 0xff)
+}
+
+/// Loads the word at `[fp, #offset]`. Offsets beyond the 12-bit range of
+/// `ldr` go through `reg` itself.
+pub fn frame_load(reg: &str, offset: i32) -> String {
+    if offset.abs() <= 4095 {
+        format!("\tldr {}, [fp, #{}]", reg, offset)
+    } else {
+        format!("\tldr {}, ={}\n\tldr {}, [fp, {}]", reg, offset, reg, reg)
+    }
+}
+
+/// Stores `reg` to `[fp, #offset]`, using r12 for offsets out of range.
+pub fn frame_store(reg: &str, offset: i32) -> String {
+    if offset.abs() <= 4095 {
+        format!("\tstr {}, [fp, #{}]", reg, offset)
+    } else {
+        format!("\tldr r12, ={}\n\tstr {}, [fp, r12]", offset, reg)
+    }
+}
+
+/// Puts the address `fp + offset` into `reg`.
+pub fn frame_address(reg: &str, offset: i32) -> String {
+    let (op, magnitude) = if offset < 0 { ("sub", -offset) } else { ("add", offset) };
+
+    if is_immediate(magnitude as u32) {
+        format!("\t{} {}, fp, #{}", op, reg, magnitude)
+    } else {
+        format!("\tldr {}, ={}\n\t{} {}, fp, {}", reg, magnitude, op, reg, reg)
+    }
+}
+
+/// `sub sp, sp, #size` for frames of any size.
+pub fn reserve_stack(size: i32) -> String {
+    if is_immediate(size as u32) {
+        format!("\tsub sp, sp, #{}", size)
+    } else {
+        format!("\tldr r12, ={}\n\tsub sp, sp, r12", size)
+    }
+}
+
+pub const DIVMOD_ROUTINE: &str = "__comfy_divmod";
+
+/// Signed 32-bit division: r0 / r1 -> quotient in r0, remainder in r1.
+/// Rounds toward zero like C; dividing by zero yields 0 with the dividend
+/// as remainder. Clobbers r2, r3 and r12.
+pub fn divmod_routine() -> String {
+    format!(
+        "{name}:\n\
+         \tpush {{r4, r5, lr}}\n\
+         \teor r4, r0, r1\n\
+         \tmov r5, r0\n\
+         \tcmp r0, #0\n\
+         \trsblt r0, r0, #0\n\
+         \tcmp r1, #0\n\
+         \trsblt r1, r1, #0\n\
+         \tbeq {name}_by_zero\n\
+         \tmov r2, #0\n\
+         \tmov r3, #0\n\
+         \tmov r12, #32\n\
+         {name}_loop:\n\
+         \tlsls r0, r0, #1\n\
+         \tadc r3, r3, r3\n\
+         \tlsl r2, r2, #1\n\
+         \tcmp r3, r1\n\
+         \tsubhs r3, r3, r1\n\
+         \torrhs r2, r2, #1\n\
+         \tsubs r12, r12, #1\n\
+         \tbne {name}_loop\n\
+         \tmov r0, r2\n\
+         \tmov r1, r3\n\
+         \tcmp r4, #0\n\
+         \trsblt r0, r0, #0\n\
+         \tcmp r5, #0\n\
+         \trsblt r1, r1, #0\n\
+         \tpop {{r4, r5, pc}}\n\
+         {name}_by_zero:\n\
+         \tmov r0, #0\n\
+         \tmov r1, r5\n\
+         \tpop {{r4, r5, pc}}\n",
+        name = DIVMOD_ROUTINE
+    )
+}
+
+pub const UDIVMOD_ROUTINE: &str = "__comfy_udivmod";
+
+/// Unsigned 32-bit division: r0 / r1 -> quotient in r0, remainder in r1.
+/// Dividing by zero yields 0 with the dividend as remainder. Clobbers r2,
+/// r3 and r12.
+pub fn udivmod_routine() -> String {
+    // The remainder can outgrow 32 bits for divisors above 2^31; the carry
+    // out of `adcs` means it's larger than the divisor either way
+    format!(
+        "{name}:\n\
+         \tcmp r1, #0\n\
+         \tbeq {name}_by_zero\n\
+         \tmov r2, #0\n\
+         \tmov r3, #0\n\
+         \tmov r12, #32\n\
+         {name}_loop:\n\
+         \tlsls r0, r0, #1\n\
+         \tadcs r3, r3, r3\n\
+         \tlsl r2, r2, #1\n\
+         \tcmpcc r3, r1\n\
+         \tsubhs r3, r3, r1\n\
+         \torrhs r2, r2, #1\n\
+         \tsubs r12, r12, #1\n\
+         \tbne {name}_loop\n\
+         \tmov r0, r2\n\
+         \tmov r1, r3\n\
+         \tbx lr\n\
+         {name}_by_zero:\n\
+         \tmov r1, r0\n\
+         \tmov r0, #0\n\
+         \tbx lr\n",
+        name = UDIVMOD_ROUTINE
+    )
+}
+
+pub const BOUNDS_PANIC_ROUTINE: &str = "__comfy_bounds_panic";
+pub const BOUNDS_PANIC_MESSAGE: &[u8] = b"panic: index out of bounds\n";
+
+/// Exit status of a program stopped by a failed bounds check.
+pub const BOUNDS_PANIC_STATUS: i32 = 101;
+
+/// Target of every failed `--bounds-checks` check: writes the string at
+/// `message` to stderr and exits with `BOUNDS_PANIC_STATUS`. Never returns.
+pub fn bounds_panic_routine(message: &str, write: u32, exit: u32) -> String {
+    format!(
+        "{name}:\n\
+         \tmov r0, #2\n\
+         \tldr r1, ={message}\n\
+         \tldr r2, ={message}_len\n\
+         {write}\n\
+         \tsvc #0\n\
+         \tmov r0, #{status}\n\
+         {exit}\n\
+         \tsvc #0\n\
+         \t.ltorg\n",
+        name = BOUNDS_PANIC_ROUTINE,
+        message = message,
+        write = load_imm("r7", write as i32),
+        exit = load_imm("r7", exit as i32),
+        status = BOUNDS_PANIC_STATUS,
+    )
+}
+
 #[allow(dead_code)]
 pub fn mov_imm(reg: &str, value: usize) -> String {
     format!("\tmov {}, #{}", reg, value)
@@ -98,8 +259,8 @@ pub fn comment(text: &str) -> String {
 }
 
 #[allow(dead_code)]
-pub fn declare_string(label: &str, value: &str) -> String {
-    format!("{}: .asciz \"{}\"", label, value)
+pub fn declare_string(label: &str, value: &[u8]) -> String {
+    format!("{}: .asciz \"{}\"", label, escape_string(value))
 }
 
 #[allow(dead_code)]
@@ -117,69 +278,37 @@ pub fn define_len(label: &str) -> String {
     format!("{}_len = .-{}", label, label)
 }
 
-#[allow(dead_code)]
-pub fn store_syscall_return_value(text: &mut Vec<String>) {
-    text.push(format!(
-        "\t@ Store syscall return value\n\tldr r4, ={}\n\tstr r0, [r4]\n",
-        RETURN_VALUE_BUF
-    ));
-}
-
-#[allow(dead_code)]
-pub fn load_syscall_return_value_into_reg(text: &mut Vec<String>) {
-    let reg = Register::R5;
-    text.push(format!(
-        "\t@ Load syscall return value into {}\n\tldr {}, ={}",
-        reg.as_str(),
-        reg.as_str(),
-        RETURN_VALUE_BUF
-    ));
-    
-    text.push(format!(
-        "\tldr {}, [{}]\n",
-        reg.as_str(),
-        reg.as_str()
-    ));
-}
-
-pub fn load_syscall_return_value_into_label(text: &mut Vec<String>, label: &str) {
-    let ptr_reg: Register = Register::R5;
-
-    text.push(format!(
-        "\t@ Load syscall return value into {}\n\
-         \tldr {}, ={}\n\
-         \tldr r0, [{}]\n",
-        label,
-        ptr_reg.as_str(),
-        RETURN_VALUE_BUF,
-        ptr_reg.as_str()
-    ));
-
-    text.push(format!(
-        "\tldr {}, ={}\n\
-         \tstr r0, [{}]\n",
-        ptr_reg.as_str(),
-        label,
-        ptr_reg.as_str()
-    ));
-}
-
 
 // ========== UTILITY FUNCTIONS ==========
 
+/// Encodes raw bytes for use inside a quoted GAS string directive. Anything
+/// that isn't printable ASCII becomes a three-digit octal escape, so NULs,
+/// control characters and multi-byte UTF-8 sequences survive unchanged.
+pub fn escape_string(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    escaped
+}
+
 pub fn generate_assembly(rodata: Vec<String>, bss: Vec<String>, text: Vec<String>) -> String {
     let mut assembly_code = String::new();
     assembly_code.push_str("\n.section .rodata\n");
     for rodata_item in rodata.iter() {
-        assembly_code.push_str("\t");
+        assembly_code.push('\t');
         assembly_code.push_str(rodata_item.as_str());
         assembly_code.push('\n');
     }
 
     assembly_code.push_str("\n.section .bss\n");
-    assembly_code.push_str(&format!("\t.comm {}, {}, {}\n", RETURN_VALUE_BUF, RETURN_VALUE_SIZE, RETURN_VALUE_BUF_ALLIGNMENT));
     for bss_item in bss.iter() {
-        assembly_code.push_str("\t");
+        assembly_code.push('\t');
         assembly_code.push_str(bss_item.as_str());
         assembly_code.push('\n');
     }
@@ -191,4 +320,17 @@ pub fn generate_assembly(rodata: Vec<String>, bss: Vec<String>, text: Vec<String
     }
 
     assembly_code
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_string_keeps_every_byte() {
+        assert_eq!(escape_string(b"plain text"), "plain text");
+        assert_eq!(escape_string(b"say \"hi\" \\o/"), "say \\\"hi\\\" \\\\o/");
+        assert_eq!(escape_string(b"\n\t\0\x7f"), "\\012\\011\\000\\177");
+        assert_eq!(escape_string("é".as_bytes()), "\\303\\251");
+    }
+}
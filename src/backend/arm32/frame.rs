@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use crate::frontend::{
+    parser::AstNode,
+    types::{Type, align_to},
+};
+
+/// `push {r4, r7, fp, lr}` in every prologue takes 16 bytes, so arguments
+/// passed on the stack start at `[fp, #16]`.
+pub const SAVED_REGISTERS_SIZE: i32 = 16;
+pub const ARGUMENT_REGISTERS: usize = 4;
+
+/// Stack frame of one function. Everything is addressed relative to fp,
+/// which points at the saved registers:
+///
+/// ```text
+///   [fp, #16 + 4n]   stack-passed argument n (5th argument onwards)
+///   [fp, #0..16]     saved r4, r7, fp, lr
+///   [fp, #-4..]      spilled r0-r3 arguments
+///                    scalar locals, one word each
+///                    buffers, rounded up to whole words and aligned
+///                    like their element type (at most 8 bytes)
+///   sp               (frame size kept 8-byte aligned)
+/// ```
+///
+/// Slots are keyed by the start offset of the declaring node, so two
+/// declarations of the same name get separate slots.
+#[derive(Debug, Default)]
+pub struct FrameLayout {
+    slots: HashMap<usize, i32>,
+    pub size: i32,
+}
+
+impl FrameLayout {
+    pub fn new(params: &[AstNode], body: &[AstNode]) -> Self {
+        let mut layout = Self::default();
+
+        for (index, param) in params.iter().enumerate() {
+            let offset = if index < ARGUMENT_REGISTERS {
+                layout.allocate(4, 4)
+            } else {
+                SAVED_REGISTERS_SIZE + 4 * (index - ARGUMENT_REGISTERS) as i32
+            };
+            layout.slots.insert(param.span().start, offset);
+        }
+
+        // Scalars first so they stay within reach of a single `ldr`/`str`
+        let mut buffers = Vec::new();
+        layout.visit(body, &mut buffers);
+        for (start, size, align) in buffers {
+            let offset = layout.allocate(size, align);
+            layout.slots.insert(start, offset);
+        }
+
+        layout.size = (layout.size + 7) & !7;
+        layout
+    }
+
+    /// fp-relative offset of the slot owned by the node starting at `start`.
+    pub fn offset(&self, start: usize) -> Option<i32> {
+        self.slots.get(&start).copied()
+    }
+
+    /// fp is 8-byte aligned, so a slot ending at a multiple of `align`
+    /// below it starts aligned as well.
+    fn allocate(&mut self, size: i32, align: i32) -> i32 {
+        self.size = align_to(self.size + align_to(size, 4), align.max(4));
+        -self.size
+    }
+
+    fn visit(&mut self, statements: &[AstNode], buffers: &mut Vec<(usize, i32, i32)>) {
+        for stmt in statements {
+            match stmt {
+                // `str` variables stay in .rodata, they are never written
+                AstNode::VariableDeclaration(_, _, None | Some(Type::Str), value, _)
+                    if matches!(**value, AstNode::String(_, _)) => {}
+                AstNode::VariableDeclaration(_, _, _, _, span) => {
+                    let offset = self.allocate(4, 4);
+                    self.slots.insert(span.start, offset);
+                }
+                AstNode::Identifier(_, count, element, span) => {
+                    let size = count.unwrap_or(1) * element.size();
+                    buffers.push((span.start, size, element.align()))
+                }
+                AstNode::If(_, then_body, else_body, _) => {
+                    self.visit(then_body, buffers);
+                    self.visit(else_body, buffers);
+                }
+                AstNode::While(_, body, _) | AstNode::Loop(body, _) => self.visit(body, buffers),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::{parser::parse, tokenizer::tokenize};
+
+    /// Frame of the first function in `source`.
+    fn layout(source: &str) -> FrameLayout {
+        let AstNode::Program(items, _) = parse(tokenize(source).unwrap()).unwrap() else {
+            unreachable!()
+        };
+        let AstNode::FunctionDefinition(_, params, _, body, _) = &items[0] else {
+            unreachable!()
+        };
+        FrameLayout::new(params, body)
+    }
+
+    /// Slot of the node starting at the first occurrence of `declaration`.
+    fn offset(layout: &FrameLayout, source: &str, declaration: &str) -> i32 {
+        layout.offset(source.find(declaration).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn scalars_come_before_buffers() {
+        let source = "fn f(a: i32, b: i32) { buf[5] big; let x = 1; if x { let y = 2; } }";
+        let layout = layout(source);
+
+        assert_eq!(offset(&layout, source, "a: i32"), -4);
+        assert_eq!(offset(&layout, source, "b: i32"), -8);
+        assert_eq!(offset(&layout, source, "let x"), -12);
+        assert_eq!(offset(&layout, source, "let y"), -16);
+        assert_eq!(offset(&layout, source, "buf[5]"), -24);
+        assert_eq!(layout.size, 24);
+    }
+
+    #[test]
+    fn stack_arguments_sit_above_the_saved_registers() {
+        let source = "fn f(a: i32, b: i32, c: i32, d: i32, e: i32, g: i32) { let x = 1; }";
+        let layout = layout(source);
+
+        assert_eq!(offset(&layout, source, "e: i32"), SAVED_REGISTERS_SIZE);
+        assert_eq!(offset(&layout, source, "g: i32"), SAVED_REGISTERS_SIZE + 4);
+        assert_eq!(offset(&layout, source, "let x"), -20);
+        assert_eq!(layout.size, 24);
+    }
+
+    #[test]
+    fn strings_need_no_slot() {
+        let source = "fn f() { let s = \"abc\"; }";
+        assert_eq!(layout(source).size, 0);
+    }
+}
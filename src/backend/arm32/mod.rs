@@ -1,3 +1,4 @@
 pub mod asm;
+pub mod frame;
 pub mod section;
 pub mod syscall_mapper;
\ No newline at end of file
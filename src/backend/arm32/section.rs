@@ -1,3 +1,5 @@
+use crate::backend::arm32::asm::escape_string;
+
 pub struct SectionWriter {
     pub rodata: Vec<String>,
     pub bss: Vec<String>,
@@ -6,13 +8,10 @@ pub struct SectionWriter {
 
 impl SectionWriter {
     pub fn new() -> Self {
-        let mut text = Vec::new();
-        text.push(".global _start".to_string());
-
         Self {
             rodata: Vec::new(),
             bss: Vec::new(),
-            text,
+            text: vec![".global _start".to_string()],
         }
     }
 
@@ -29,15 +28,12 @@ impl SectionWriter {
 
     // ====== BSS SECTION HELPERS ======
 
+    #[allow(dead_code)]
     pub fn declare_bss(&mut self, label: &str, size: usize) {
         self.bss.push(format!(".lcomm {}, {}", label, size));
     }
 
-    pub fn declare_bss_with_name_prefix(&mut self, prefix: &str, name: &str, size: i32) {
-        self.bss
-            .push(format!(".lcomm {}_{}, {}", prefix, name, size));
-    }
-
+    #[allow(dead_code)]
     pub fn declare_bss_with_len(&mut self, label: &str, size: i32) {
         self.bss.push(format!(".lcomm {}, {}", label, size));
         self.bss.push(format!("{}_len = {}", label, size));
@@ -46,17 +42,24 @@ impl SectionWriter {
     // ====== RODATA SECTION HELPERS ======
 
     #[allow(dead_code)]
-    pub fn push_rodata_str(&mut self, label: &str, value: &str) {
-        self.rodata.push(format!("{}: .asciz \"{}\"", label, value));
+    pub fn push_rodata_str(&mut self, label: &str, value: &[u8]) {
+        self.rodata
+            .push(format!("{}: .asciz \"{}\"", label, escape_string(value)));
     }
 
+    #[allow(dead_code)]
     pub fn push_rodata_word(&mut self, label: &str, value: i32) {
         self.rodata.push(format!("{}: .word {}", label, value));
     }
 
-    pub fn push_rodata_str_with_len(&mut self, label: &str, value: &str) {
-        self.rodata.push(format!("{}: .asciz \"{}\"", label, value));
+    /// Emits the string followed by its length symbol. The terminating NUL
+    /// comes after the length is taken, so it isn't counted but paths can
+    /// still be handed to the kernel as C strings.
+    pub fn push_rodata_str_with_len(&mut self, label: &str, value: &[u8]) {
+        self.rodata
+            .push(format!("{}: .ascii \"{}\"", label, escape_string(value)));
         self.rodata.push(format!("{}_len = .-{}", label, label));
+        self.rodata.push(".byte 0".to_string());
     }
 
     #[allow(dead_code)]
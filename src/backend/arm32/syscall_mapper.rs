@@ -1,4 +1,8 @@
-use std::collections::HashMap;
+use crate::{
+    backend::syscalls::find_syscall,
+    extra::diagnostic::{CompileResult, Diagnostic, E_UNAVAILABLE_SYSCALL},
+    frontend::span::Span,
+};
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
@@ -9,24 +13,31 @@ pub enum Architecture {
     X86_64,
 }
 
-fn arm32_syscalls() -> HashMap<&'static str, u32> {
-    HashMap::from([
-        ("exit", 1),
-        ("read", 3),
-        ("write", 4),
-        ("open", 5),
-    ])
-}
-
 pub fn get_syscall_num(arch: Architecture, name: &str) -> Option<u32> {
+    let numbers = find_syscall(name)?.numbers;
+
     match arch {
-        Architecture::Arm32 => arm32_syscalls().get(name).cloned(),
-        Architecture::Arm64 => panic!("ARM64 syscalls not implemented yet"),
-        Architecture::X86 => panic!("X86 syscalls not implemented yet"),
-        Architecture::X86_64 => panic!("X86_64 syscalls not implemented yet"),
+        Architecture::Arm32 => numbers.arm32,
+        Architecture::Arm64 => numbers.arm64,
+        Architecture::X86 => numbers.x86,
+        Architecture::X86_64 => numbers.x86_64,
     }
 }
 
-pub fn get_syscall_num_or_panic(arch: Architecture, name: &str) -> u32 {
-    get_syscall_num(arch, name).unwrap_or_else(|| panic!("Unknown syscall `{}` for {:?}", name, arch))
-}
\ No newline at end of file
+pub fn get_syscall_num_or_error(arch: Architecture, name: &str, span: Span) -> CompileResult<u32> {
+    get_syscall_num(arch, name).ok_or_else(|| {
+        let diagnostic = Diagnostic::error(
+            E_UNAVAILABLE_SYSCALL,
+            format!("syscall `${}` is not available for {:?}", name, arch),
+            span,
+        )
+        .with_label("used here");
+
+        let diagnostic = if find_syscall(name).is_some() {
+            diagnostic.with_note(format!("{:?} has no `{}` syscall", arch, name))
+        } else {
+            diagnostic
+        };
+        Box::new(diagnostic)
+    })
+}
@@ -1,206 +1,1453 @@
+use std::collections::HashMap;
+
 use crate::{
     backend::arm32::{
         asm::{
-            load_syscall_return_value_into_label, store_syscall_return_value, syscall_1arg,
-            syscall_2args, syscall_3args,
+            BOUNDS_PANIC_MESSAGE, BOUNDS_PANIC_ROUTINE, DIVMOD_ROUTINE, UDIVMOD_ROUTINE,
+            bounds_panic_routine, divmod_routine, frame_address, frame_load, frame_store,
+            is_immediate, load_imm, reserve_stack, udivmod_routine,
         },
+        frame::{ARGUMENT_REGISTERS, FrameLayout},
         section,
-        syscall_mapper::{Architecture, get_syscall_num_or_panic},
+        syscall_mapper::{Architecture, get_syscall_num_or_error},
+    },
+    backend::syscalls::{ArgKind, ReturnKind, find_syscall},
+    extra::{
+        diagnostic::{
+            CompileResult, Diagnostic, E_ARGUMENT_COUNT, E_OUTSIDE_LOOP,
+            E_UNKNOWN_FUNCTION, E_UNKNOWN_SYSCALL, E_UNKNOWN_VARIABLE, E_UNSUPPORTED,
+        },
+        utils::generate_str_varname,
     },
-    extra::utils::generate_str_varname,
-    frontend::parser::AstNode,
-    frontend::tokenizer::Token,
+    frontend::checker::TypeTable,
+    frontend::parser::{AstNode, BinaryOp, UnaryOp},
+    frontend::resolver::{Resolution, SymbolId, SymbolKind},
+    frontend::span::Span,
+    frontend::types::Type,
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BindingKind {
+    Value,
+    Parameter,
+    Buffer(i32), // size in bytes
+    String,      // string literal in read-only data
+}
+
+impl BindingKind {
+    /// Scalars are used by value. Strings and buffers are only ever used
+    /// through their address.
+    fn is_scalar(&self) -> bool {
+        matches!(self, BindingKind::Value | BindingKind::Parameter)
+    }
+}
+
+/// Where the bytes behind a binding live.
+#[derive(Debug, Clone, PartialEq)]
+enum Storage {
+    Frame(i32),    // fp-relative slot from the function's `FrameLayout`
+    Label(String), // read-only data, e.g. string literals
+}
+
+/// What the generator remembers about a name declared in the current function.
+#[derive(Debug, Clone)]
+struct Binding {
+    kind: BindingKind,
+    ty: Type,
+    span: Span,
+    storage: Storage,
+}
+
+/// Registers saved by every prologue. r7 holds syscall numbers and r4 is
+/// never used, it only keeps the push a multiple of 8 bytes. The epilogue
+/// pops the saved lr straight into pc.
+const SAVED_REGISTERS: &str = "{r4, r7, fp, lr}";
+const RESTORED_REGISTERS: &str = "{r4, r7, fp, pc}";
+
+/// Branch targets of an enclosing loop.
+struct LoopLabels {
+    continue_label: String,
+    break_label: String,
+}
+
 pub struct Generator {
     pub section_writer: section::SectionWriter,
     last_fun_name: String,
     arch: Architecture,
+    label_counter: usize,
+    stack_depth: usize, // bytes pushed below the frame by the code emitted so far
+    uses_divmod: bool,
+    uses_udivmod: bool,
+    bounds_checks: bool, // emit runtime checks for slices and syscall lengths
+    uses_bounds_panic: bool,
+    resolution: Resolution,
+    types: TypeTable,
+    bindings: HashMap<SymbolId, Binding>,
+    loops: Vec<LoopLabels>,
+    return_label: Option<String>, // set while generating a function body
+    return_type: Type,
+    frame: FrameLayout,
 }
 
-pub fn generate(ast_nodes: &AstNode, arch: Architecture) -> Generator {
-    let mut generator = Generator::new(arch);
-    generator.generate(ast_nodes);
-    generator
+pub fn generate(
+    ast_nodes: &AstNode,
+    resolution: Resolution,
+    types: TypeTable,
+    arch: Architecture,
+    bounds_checks: bool,
+) -> CompileResult<Generator> {
+    let mut generator = Generator::new(resolution, types, arch);
+    generator.bounds_checks = bounds_checks;
+    generator.generate(ast_nodes)?;
+
+    if generator.uses_divmod {
+        generator.section_writer.push_text(divmod_routine());
+    }
+    if generator.uses_udivmod {
+        generator.section_writer.push_text(udivmod_routine());
+    }
+    if generator.uses_bounds_panic {
+        let span = ast_nodes.span();
+        let write = get_syscall_num_or_error(arch, "write", span)?;
+        let exit = get_syscall_num_or_error(arch, "exit", span)?;
+        let message = format!("{}_message", BOUNDS_PANIC_ROUTINE);
+
+        let writer = &mut generator.section_writer;
+        writer.push_rodata_str_with_len(&message, BOUNDS_PANIC_MESSAGE);
+        writer.push_text(bounds_panic_routine(&message, write, exit));
+    }
+
+    Ok(generator)
+}
+
+fn unsupported(message: impl Into<String>, span: Span) -> Diagnostic {
+    Diagnostic::error(E_UNSUPPORTED, message, span)
+}
+
+/// ARM condition code for a comparison operator, signed or unsigned.
+fn condition_code(op: BinaryOp, unsigned: bool) -> Option<&'static str> {
+    match (op, unsigned) {
+        (BinaryOp::Eq, _) => Some("eq"),
+        (BinaryOp::Ne, _) => Some("ne"),
+        (BinaryOp::Lt, false) => Some("lt"),
+        (BinaryOp::Le, false) => Some("le"),
+        (BinaryOp::Gt, false) => Some("gt"),
+        (BinaryOp::Ge, false) => Some("ge"),
+        (BinaryOp::Lt, true) => Some("lo"),
+        (BinaryOp::Le, true) => Some("ls"),
+        (BinaryOp::Gt, true) => Some("hi"),
+        (BinaryOp::Ge, true) => Some("hs"),
+        _ => None,
+    }
+}
+
+/// `main` is the program entry point, everything else keeps its name.
+fn function_label(name: &str) -> String {
+    if name == "main" {
+        "_start".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+fn inverse_condition(cond: &str) -> &'static str {
+    match cond {
+        "eq" => "ne",
+        "ne" => "eq",
+        "lt" => "ge",
+        "le" => "gt",
+        "gt" => "le",
+        "ge" => "lt",
+        "lo" => "hs",
+        "ls" => "hi",
+        "hi" => "ls",
+        _ => "lo",
+    }
+}
+
+/// Load of one element of type `ty`, extended to a full register.
+fn load_instruction(ty: &Type) -> &'static str {
+    match ty {
+        Type::U8 | Type::Bool => "ldrb",
+        Type::I8 => "ldrsb",
+        Type::U16 => "ldrh",
+        Type::I16 => "ldrsh",
+        _ => "ldr",
+    }
+}
+
+/// Store of one element of type `ty`; narrower stores drop the upper bits.
+fn store_instruction(ty: &Type) -> &'static str {
+    match ty.size() {
+        1 => "strb",
+        2 => "strh",
+        _ => "str",
+    }
+}
+
+/// Truncates `reg` to the width of `ty`, sign- or zero-extending it back to
+/// a full register. Words and pointers are left alone.
+fn narrow(reg: &str, ty: &Type) -> Option<String> {
+    let (shift, extend) = match ty {
+        Type::U8 | Type::Bool => return Some(format!("\tand {}, {}, #255", reg, reg)),
+        Type::I8 => (24, "asr"),
+        Type::U16 => (16, "lsr"),
+        Type::I16 => (16, "asr"),
+        _ => return None,
+    };
+    Some(format!(
+        "\tlsl {reg}, {reg}, #{shift}\n\t{extend} {reg}, {reg}, #{shift}",
+        reg = reg,
+        shift = shift,
+        extend = extend
+    ))
 }
 
 impl Generator {
-    pub fn new(arch: Architecture) -> Self {
-        let self_data = Self {
+    pub fn new(resolution: Resolution, types: TypeTable, arch: Architecture) -> Self {
+        Self {
             section_writer: section::SectionWriter::new(),
             last_fun_name: String::new(),
             arch,
+            stack_depth: 0,
+            label_counter: 0,
+            uses_divmod: false,
+            uses_udivmod: false,
+            bounds_checks: false,
+            uses_bounds_panic: false,
+            resolution,
+            types,
+            bindings: HashMap::new(),
+            loops: Vec::new(),
+            return_label: None,
+            return_type: Type::I32,
+            frame: FrameLayout::default(),
+        }
+    }
+
+    /// Gives the symbol declared at `span` its storage.
+    fn declare(&mut self, kind: BindingKind, span: Span, storage: Storage) {
+        let Some(id) = self.resolution.id_at(span) else {
+            return;
         };
+        let ty = self.types.symbol(id).cloned().unwrap_or(Type::I32);
+        self.bindings.insert(
+            id,
+            Binding {
+                kind,
+                ty,
+                span,
+                storage,
+            },
+        );
+    }
+
+    /// Stack slot the frame layout pass reserved for the declaration at `span`.
+    fn frame_slot(&self, span: Span) -> CompileResult<i32> {
+        match self.frame.offset(span.start) {
+            Some(offset) => Ok(offset),
+            None => Err(unsupported("variables can only be declared inside a function", span)
+                .with_note("move this declaration into `fn main() { .. }` or another function")
+                .into()),
+        }
+    }
 
-        self_data
+    fn emit(&mut self, line: impl Into<String>) {
+        self.section_writer.push_text(line);
     }
 
-    fn generate(&mut self, ast: &AstNode) {
+    /// Fresh local label scoped to the current function, e.g. `.L_start_and_3`.
+    fn new_label(&mut self, name: &str) -> String {
+        let label = format!(".L{}_{}_{}", self.last_fun_name, name, self.label_counter);
+        self.label_counter += 1;
+        label
+    }
+
+    fn generate(&mut self, ast: &AstNode) -> CompileResult<()> {
         match ast {
-            AstNode::Program(statements) => {
+            AstNode::Program(statements, _) => {
                 for stmt in statements {
-                    self.generate(stmt);
+                    self.generate(stmt)?;
                 }
             }
 
-            AstNode::FunctionDefinition(name, params, body) => {
-                let fun_name = if name == "main" {
-                    "_start".to_string()
-                } else {
-                    name.clone()
-                };
+            AstNode::FunctionDefinition(name, params, returns, body, span) => {
+                self.return_type = returns.clone().unwrap_or(Type::I32);
+                self.generate_function(name, params, body, *span)?;
+            }
 
-                self.last_fun_name = fun_name.clone();
-                self.section_writer.push_text(format!("{}:", fun_name));
+            AstNode::Return(value, span) => {
+                let Some(return_label) = self.return_label.clone() else {
+                    return Err(unsupported("`return` outside of a function", *span).into());
+                };
 
-                // Declare params in .bss
-                for param in params {
-                    if let AstNode::Identifier(param_name, size) = param {
-                        self.section_writer
-                            .declare_bss_with_name_prefix(&fun_name, param_name, *size);
+                match value {
+                    Some(value) => {
+                        self.generate_expression(value)?;
+                        self.emit_narrow(&self.return_type.clone());
                     }
+                    None => self.emit("\tmov r0, #0"),
                 }
-
-                for stmt in body {
-                    self.generate(stmt);
-                }
+                self.emit(format!("\tb {}", return_label));
             }
 
-            AstNode::VariableDeclaration(name, value) => {
-                let label = format!("{}_{}", self.last_fun_name, name);
+            AstNode::Call(_, _, _) => self.generate_expression(ast)?,
 
-                match &**value {
-                    AstNode::Number(n) => {
-                        self.section_writer.push_rodata_word(&label, *n);
+            AstNode::VariableDeclaration(_, mutable, _, value, span) => {
+                // `str` variables are constant data; everything else gets a stack slot
+                // that is written at runtime, so a declaration inside a loop starts over
+                // on every iteration and recursive calls don't share locals
+                let is_str = self.declared_type(*span) == Some(Type::Str);
+                match (&**value, *mutable) {
+                    (AstNode::String(_, string_span), true) if is_str => {
+                        return Err(unsupported("strings can't be mutable", *span)
+                            .with_secondary(*string_span, "string value")
+                            .with_note("copy it into a buffer with `buf[N] name;` to change it")
+                            .into());
                     }
-                    AstNode::String(s) => {
+                    (AstNode::String(s, _), false) if is_str => {
+                        let label = generate_str_varname();
                         self.section_writer.push_rodata_str_with_len(&label, s);
+                        let storage = Storage::Label(label);
+                        self.declare(BindingKind::String, *span, storage);
                     }
-                    AstNode::Syscall(_, _) => {
-                        self.section_writer.declare_bss(&label, 4);
-                        self.generate(value);
-                        load_syscall_return_value_into_label(&mut self.section_writer.text, &label);
+                    _ => {
+                        let slot = Storage::Frame(self.frame_slot(*span)?);
+                        let ty = self.declared_type(*span).unwrap_or(Type::I32);
+                        self.store_value(&slot, &ty, value)?;
+                        self.declare(BindingKind::Value, *span, slot);
                     }
-                    _ => panic!("Unsupported variable declaration value: {:?}", value),
                 }
             }
 
-            AstNode::Identifier(name, size) => {
-                let label = format!("{}_{}", self.last_fun_name, name);
-                self.section_writer.declare_bss_with_len(&label, *size);
+            AstNode::Assignment(name, value, span) => {
+                let binding = self.lookup(name, *span)?;
+                self.store_value(&binding.storage, &binding.ty, value)?;
             }
 
-            AstNode::Syscall(name, inner) => match name.as_str() {
-                "write" => self.generate_write(inner),
-                "read" => self.generate_read(inner),
-                "open" => self.generate_open(inner),
-                "exit" => self.generate_exit(inner),
-                _ => panic!("Unknown syscall: {}", name),
-            },
+            AstNode::If(condition, then_body, else_body, _) => {
+                let else_label = self.new_label("else");
+                let end_label = self.new_label("endif");
+
+                self.generate_branch_if_false(condition, &else_label)?;
+                for stmt in then_body {
+                    self.generate(stmt)?;
+                }
+
+                if else_body.is_empty() {
+                    self.emit(format!("{}:", else_label));
+                } else {
+                    self.emit(format!("\tb {}", end_label));
+                    self.emit(format!("{}:", else_label));
+                    for stmt in else_body {
+                        self.generate(stmt)?;
+                    }
+                    self.emit(format!("{}:", end_label));
+                }
+            }
+
+            AstNode::While(condition, body, _) => {
+                let continue_label = self.new_label("while");
+                let break_label = self.new_label("endwhile");
+
+                self.emit(format!("{}:", continue_label));
+                self.generate_branch_if_false(condition, &break_label)?;
+                self.generate_loop_body(body, &continue_label, &break_label)?;
+            }
+
+            AstNode::Loop(body, _) => {
+                let continue_label = self.new_label("loop");
+                let break_label = self.new_label("endloop");
 
-            _ => panic!("Unsupported AST node in code generation: {:?}", ast),
+                self.emit(format!("{}:", continue_label));
+                self.generate_loop_body(body, &continue_label, &break_label)?;
+            }
+
+            AstNode::Break(span) | AstNode::Continue(span) => {
+                let is_break = matches!(ast, AstNode::Break(_));
+                let keyword = if is_break { "break" } else { "continue" };
+
+                let Some(labels) = self.loops.last() else {
+                    return Err(Diagnostic::error(
+                        E_OUTSIDE_LOOP,
+                        format!("`{}` outside of a loop", keyword),
+                        *span,
+                    )
+                    .with_label(format!("cannot `{}` outside of a loop", keyword))
+                    .into());
+                };
+
+                let target = if is_break {
+                    labels.break_label.clone()
+                } else {
+                    labels.continue_label.clone()
+                };
+                self.emit(format!("\tb {}", target));
+            }
+
+            AstNode::Identifier(_, count, element, span) => {
+                let slot = Storage::Frame(self.frame_slot(*span)?);
+                let size = count.unwrap_or(1) * element.size();
+                self.declare(BindingKind::Buffer(size), *span, slot);
+            }
+
+            // Both were evaluated by the parser and live on in the nodes using them
+            AstNode::StructDefinition(name, span) | AstNode::ConstDefinition(name, span) => {
+                let what = match ast {
+                    AstNode::StructDefinition(_, _) => "struct",
+                    _ => "constant",
+                };
+                if self.return_label.is_some() {
+                    let message = format!("{} `{}` can't be declared in a function", what, name);
+                    return Err(unsupported(message, *span)
+                        .with_note(format!("move this {} to the top level", what))
+                        .into());
+                }
+            }
+
+            AstNode::DerefAssignment(pointer, value, span) => {
+                let target = match self.types.expression(pointer).map(Type::decay) {
+                    Some(Type::Ptr(target)) => *target,
+                    _ => return Err(unsupported("can only assign through a pointer", *span).into()),
+                };
+
+                self.generate_expression(pointer)?;
+                self.push_temporary("r0");
+                self.generate_expression(value)?;
+                self.pop_temporary("r1");
+                self.emit(format!("\t{} r0, [r1]", store_instruction(&target)));
+            }
+
+            AstNode::IndexAssignment(name, index, value, span) => {
+                let element = self.generate_element_address(name, index, *span)?;
+                self.push_temporary("r0");
+                self.generate_expression(value)?;
+                self.pop_temporary("r1");
+                self.emit(format!("\t{} r0, [r1]", store_instruction(&element)));
+            }
+
+            AstNode::FieldAssignment(base, field, value, _) => {
+                let ty = self.generate_field_address(base, field)?;
+                self.push_temporary("r0");
+                self.generate_expression(value)?;
+                self.pop_temporary("r1");
+                self.emit(format!("\t{} r0, [r1]", store_instruction(&ty)));
+            }
+
+            AstNode::Syscall(name, args, span) => self.generate_syscall(name, args, *span)?,
+
+            AstNode::RawSyscall(_, _, _) => self.generate_raw_syscall(ast)?,
+
+            _ => {
+                return Err(unsupported(
+                    "this construct is not supported in code generation",
+                    ast.span(),
+                )
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emits a function with an AAPCS frame: the first four arguments arrive
+    /// in r0-r3 and get spilled below fp, the rest stay where the caller
+    /// pushed them above the saved registers. The result is returned in r0.
+    fn generate_function(
+        &mut self,
+        name: &str,
+        params: &[AstNode],
+        body: &[AstNode],
+        span: Span,
+    ) -> CompileResult<()> {
+        if self.return_label.is_some() {
+            return Err(unsupported("functions can't be nested", span)
+                .with_note("move this function to the top level")
+                .into());
+        }
+
+        let fun_name = function_label(name);
+        self.last_fun_name = fun_name.clone();
+        self.bindings.clear();
+        self.stack_depth = 0;
+
+        let return_label = self.new_label("return");
+        self.return_label = Some(return_label.clone());
+
+        self.frame = FrameLayout::new(params, body);
+
+        self.emit(format!("{}:", fun_name));
+        self.emit(format!("\tpush {}", SAVED_REGISTERS));
+        self.emit("\tmov fp, sp");
+        if self.frame.size > 0 {
+            self.emit(reserve_stack(self.frame.size));
+        }
+
+        for (index, param) in params.iter().enumerate() {
+            let AstNode::Parameter(_, _, param_span) = param else {
+                return Err(unsupported("invalid parameter node", param.span()).into());
+            };
+
+            let offset = self.frame_slot(*param_span)?;
+            if index < ARGUMENT_REGISTERS {
+                self.emit(frame_store(&format!("r{}", index), offset));
+            }
+
+            let slot = Storage::Frame(offset);
+            self.declare(BindingKind::Parameter, *param_span, slot);
         }
+
+        for stmt in body {
+            self.generate(stmt)?;
+        }
+
+        // Falling off the end returns 0
+        self.emit("\tmov r0, #0");
+        self.emit(format!("{}:", return_label));
+
+        if fun_name == "_start" {
+            // There is no caller to return to, so the result becomes the exit code
+            let exit = get_syscall_num_or_error(self.arch, "exit", span)?;
+            self.emit(format!("\tmov r7, #{}", exit));
+            self.emit("\tsvc #0");
+        } else {
+            self.emit("\tmov sp, fp");
+            self.emit(format!("\tpop {}", RESTORED_REGISTERS));
+        }
+        self.emit("\t.ltorg\n");
+
+        self.return_label = None;
+        Ok(())
     }
 
-    fn generate_write(&mut self, inner: &AstNode) {
-        let (fd, data) = match inner {
-            AstNode::Write(fd, token) => (fd, token),
-            _ => panic!("Invalid write syscall inner node"),
+    /// Calls a user-defined function. Arguments are evaluated left to right
+    /// into a reserved block on the stack; the first four are then popped
+    /// into r0-r3 and the rest stay on the stack for the callee.
+    fn generate_call(&mut self, name: &str, args: &[AstNode], span: Span) -> CompileResult<()> {
+        let function = match self.resolution.symbol_at(span) {
+            Some(symbol) => symbol.clone(),
+            None => {
+                return Err(Diagnostic::error(
+                    E_UNKNOWN_FUNCTION,
+                    format!("cannot find function `{}`", name),
+                    span,
+                )
+                .with_label("not defined anywhere in this file")
+                .into());
+            }
+        };
+        let SymbolKind::Function(arity) = function.kind else {
+            return Err(unsupported(format!("`{}` is not a function", name), span).into());
         };
 
-        let syscall_number: u32 = get_syscall_num_or_panic(self.arch, "write");
-        let fd_str = match fd {
-            Token::Number(n) => n.to_string(),
-            Token::Identifier(id) => id.clone(),
-            _ => panic!("Unsupported file descriptor type: {:?}", fd),
+        if arity != args.len() {
+            return Err(Diagnostic::error(
+                E_ARGUMENT_COUNT,
+                format!(
+                    "function `{}` takes {} argument(s) but {} were supplied",
+                    name,
+                    arity,
+                    args.len()
+                ),
+                span,
+            )
+            .with_label(format!("expected {} argument(s)", arity))
+            .with_secondary(function.span, "function defined here")
+            .into());
+        }
+
+        let params = match self.resolution.id_at(span).and_then(|id| self.types.signature(id)) {
+            Some(signature) => signature.params.clone(),
+            None => Vec::new(),
         };
 
-        match data {
-            Token::String(s) => {
-                let var = generate_str_varname();
-                self.section_writer.push_rodata_str_with_len(&var, s);
+        // AAPCS wants sp 8-byte aligned at the call. Whatever is left on the
+        // stack by then, pending temporaries and the stack-passed arguments,
+        // gets padded above the arguments, where the callee won't look
+        let stack_args = args.len().saturating_sub(ARGUMENT_REGISTERS) * 4;
+        let padding = (self.stack_depth + stack_args) % 8;
+
+        if !args.is_empty() || padding != 0 {
+            self.reserve_arguments(args.len() * 4 + padding);
+            for (index, arg) in args.iter().enumerate() {
+                self.generate_expression(arg)?;
+                if let Some(ty) = params.get(index) {
+                    self.emit_narrow(ty);
+                }
+                self.emit(format!("\tstr r0, [sp, #{}]", index * 4));
+            }
+
+            self.pop_argument_registers(args.len().min(ARGUMENT_REGISTERS));
+        }
+
+        self.emit(format!("\tbl {}", function_label(name)));
+
+        if stack_args + padding != 0 {
+            self.emit(format!("\tadd sp, sp, #{}", stack_args + padding));
+            self.stack_depth -= stack_args + padding;
+        }
+
+        Ok(())
+    }
+
+    /// Parks `reg` on the stack, e.g. the left operand while the right one
+    /// is evaluated.
+    fn push_temporary(&mut self, reg: &str) {
+        self.emit(format!("\tpush {{{}}}", reg));
+        self.stack_depth += 4;
+    }
+
+    fn pop_temporary(&mut self, reg: &str) {
+        self.emit(format!("\tpop {{{}}}", reg));
+        self.stack_depth -= 4;
+    }
+
+    /// Reserves `size` bytes for outgoing arguments.
+    fn reserve_arguments(&mut self, size: usize) {
+        self.emit(format!("\tsub sp, sp, #{}", size));
+        self.stack_depth += size;
+    }
+
+    /// Pops the first `count` words on the stack into r0, r1, ...
+    fn pop_argument_registers(&mut self, count: usize) {
+        self.stack_depth -= count * 4;
+        match count {
+            0 => {}
+            1 => self.emit("\tpop {r0}"),
+            2 => self.emit("\tpop {r0, r1}"),
+            n => self.emit(format!("\tpop {{r0-r{}}}", n - 1)),
+        }
+    }
+
+    /// Emits a loop body that jumps back to `continue_label`, followed by
+    /// `break_label`. `break`/`continue` inside resolve to the innermost loop.
+    fn generate_loop_body(
+        &mut self,
+        body: &[AstNode],
+        continue_label: &str,
+        break_label: &str,
+    ) -> CompileResult<()> {
+        self.loops.push(LoopLabels {
+            continue_label: continue_label.to_string(),
+            break_label: break_label.to_string(),
+        });
+
+        for stmt in body {
+            self.generate(stmt)?;
+        }
+
+        self.loops.pop();
+        self.emit(format!("\tb {}", continue_label));
+        self.emit(format!("{}:", break_label));
+
+        Ok(())
+    }
+
+    /// Evaluates `value` as an rvalue of type `ty` and stores it through the
+    /// lvalue `storage`.
+    fn store_value(&mut self, storage: &Storage, ty: &Type, value: &AstNode) -> CompileResult<()> {
+        self.generate_expression(value)?;
+        self.emit_narrow(ty);
+
+        match storage {
+            Storage::Frame(offset) => self.emit(frame_store("r0", *offset)),
+            Storage::Label(_) => {
+                self.load_address("r1", storage);
+                self.emit("\tstr r0, [r1]");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads `name` as an rvalue into `reg`: scalars are read from their
+    /// slot, strings and buffers evaluate to their address.
+    fn load_variable(&mut self, reg: &str, name: &str, span: Span) -> CompileResult<()> {
+        let binding = self.lookup(name, span)?;
+
+        match binding.storage {
+            Storage::Frame(offset) if binding.kind.is_scalar() => {
+                self.emit(frame_load(reg, offset));
+                // A store through a pointer may have written just the low bytes
+                if let Some(code) = narrow(reg, &binding.ty) {
+                    self.emit(code);
+                }
+            }
+            Storage::Label(label) if binding.kind.is_scalar() => {
+                self.emit(format!("\tldr {}, ={}", reg, label));
+                self.emit(format!("\tldr {}, [{}]", reg, reg));
+            }
+            storage => self.load_address(reg, &storage),
+        }
+
+        Ok(())
+    }
+
+    /// Truncates r0 to `ty` before it is stored or passed on.
+    fn emit_narrow(&mut self, ty: &Type) {
+        if let Some(code) = narrow("r0", ty) {
+            self.emit(code);
+        }
+    }
+
+    /// Type the checker gave the symbol declared at `span`.
+    fn declared_type(&self, span: Span) -> Option<Type> {
+        let id = self.resolution.id_at(span)?;
+        self.types.symbol(id).cloned()
+    }
+
+    /// Whether a comparison or shift of `lhs` and `rhs` is unsigned, i.e.
+    /// either side is a `u32` or a pointer.
+    fn is_unsigned(&self, lhs: &AstNode, rhs: &AstNode) -> bool {
+        [lhs, rhs].into_iter().any(|operand| {
+            matches!(
+                self.types.expression(operand).map(Type::decay),
+                Some(Type::U32 | Type::Ptr(_))
+            )
+        })
+    }
+
+    /// Loads the address of `storage`, i.e. the lvalue of whatever lives there.
+    fn load_address(&mut self, reg: &str, storage: &Storage) {
+        match storage {
+            Storage::Frame(offset) => self.emit(frame_address(reg, *offset)),
+            Storage::Label(label) => self.emit(format!("\tldr {}, ={}", reg, label)),
+        }
+    }
+
+    /// Loads the address of the string or buffer `name` into `reg` and its
+    /// length in bytes into `len_reg`.
+    fn load_bytes(
+        &mut self,
+        reg: &str,
+        len_reg: &str,
+        name: &str,
+        span: Span,
+    ) -> CompileResult<()> {
+        let binding = self.lookup(name, span)?;
 
-                match fd {
-                    Token::Number(n) => {
-                        let instr = syscall_3args(syscall_number, &n.to_string(), &var, &format!("{}_len", var));
-                        self.section_writer.push_text(&instr);
+        match (binding.kind, &binding.storage) {
+            (BindingKind::Buffer(size), storage) => {
+                self.load_address(reg, storage);
+                self.emit(load_imm(len_reg, size));
+            }
+            (BindingKind::String, Storage::Label(label)) => {
+                self.emit(format!("\tldr {}, ={}", reg, label));
+                self.emit(format!("\tldr {}, ={}_len", len_reg, label));
+            }
+            _ => {
+                return Err(unsupported(
+                    format!("`{}` is a number, not a string or buffer", name),
+                    span,
+                )
+                .with_secondary(binding.span, "declared here")
+                .into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Binding of the symbol the resolver attached to the use at `span`.
+    fn lookup(&self, name: &str, span: Span) -> CompileResult<Binding> {
+        let binding = self.resolution.id_at(span).and_then(|id| self.bindings.get(&id));
+        match binding {
+            Some(binding) => Ok(binding.clone()),
+            None => Err(Diagnostic::error(
+                E_UNKNOWN_VARIABLE,
+                format!("cannot find value `{}` in this function", name),
+                span,
+            )
+            .with_label("not declared in this function")
+            .into()),
+        }
+    }
+
+    /// Jumps to `label` when `condition` is false. Comparisons branch straight
+    /// off the flags instead of materializing a 0/1 first.
+    fn generate_branch_if_false(&mut self, condition: &AstNode, label: &str) -> CompileResult<()> {
+        if let AstNode::Binary(op, lhs, rhs, _) = condition
+            && let Some(cond) = condition_code(*op, self.is_unsigned(lhs, rhs))
+        {
+            self.generate_operands(lhs, rhs)?;
+            self.emit("\tcmp r0, r1");
+            self.emit(format!("\tb{} {}", inverse_condition(cond), label));
+        } else {
+            self.generate_expression(condition)?;
+            self.emit("\tcmp r0, #0");
+            self.emit(format!("\tbeq {}", label));
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates `lhs` into r0 and `rhs` into r1.
+    fn generate_operands(&mut self, lhs: &AstNode, rhs: &AstNode) -> CompileResult<()> {
+        self.generate_expression(lhs)?;
+        self.push_temporary("r0");
+        self.generate_expression(rhs)?;
+        self.emit("\tmov r1, r0");
+        self.pop_temporary("r0");
+
+        Ok(())
+    }
+
+    /// Evaluates an expression into r0. Intermediate values are parked on the
+    /// stack, so nothing besides r0-r3 and r12 is clobbered.
+    fn generate_expression(&mut self, expr: &AstNode) -> CompileResult<()> {
+        match expr {
+            // Values past i32::MAX are `u32`s and keep their bit pattern
+            AstNode::Number(n, _) | AstNode::Constant(_, n, _, _) => {
+                self.emit(load_imm("r0", *n as i32))
+            }
+
+            AstNode::Bool(value, _) => self.emit(format!("\tmov r0, #{}", u8::from(*value))),
+
+            // A string literal on its own evaluates to its address
+            AstNode::String(s, _) => {
+                let label = generate_str_varname();
+                self.section_writer.push_rodata_str_with_len(&label, s);
+                self.emit(format!("\tldr r0, ={}", label));
+            }
+
+            AstNode::Variable(name, span) => self.load_variable("r0", name, *span)?,
+
+            AstNode::Call(name, args, span) => self.generate_call(name, args, *span)?,
+
+            AstNode::Index(name, index, span) => {
+                let element = self.generate_element_address(name, index, *span)?;
+                self.emit_load(&element);
+            }
+
+            AstNode::Field(base, field, _) => {
+                let ty = self.generate_field_address(base, field)?;
+                self.emit_load(&ty);
+            }
+
+            AstNode::Syscall(name, args, span) => {
+                if find_syscall(name).is_some_and(|spec| spec.returns == ReturnKind::Never) {
+                    return Err(unsupported(format!("`${}` never returns a value", name), *span)
+                        .with_label("used as a value")
+                        .into());
+                }
+                self.generate_syscall(name, args, *span)?
+            }
+
+            AstNode::RawSyscall(_, _, _) => self.generate_raw_syscall(expr)?,
+
+            AstNode::Unary(UnaryOp::AddressOf, operand, _) => self.generate_address(operand)?,
+
+            AstNode::Unary(UnaryOp::Deref, operand, _) => {
+                let target = self.types.expression(expr).cloned().unwrap_or(Type::I32);
+                self.generate_expression(operand)?;
+                self.emit_load(&target);
+            }
+
+            AstNode::Unary(op, operand, _) => {
+                self.generate_expression(operand)?;
+                match op {
+                    UnaryOp::Neg => self.emit("\trsb r0, r0, #0"),
+                    UnaryOp::Not => {
+                        self.emit("\tcmp r0, #0");
+                        self.emit("\tmoveq r0, #1");
+                        self.emit("\tmovne r0, #0");
+                    }
+                    UnaryOp::AddressOf | UnaryOp::Deref => unreachable!(),
+                }
+            }
+
+            // `&&` and `||` short-circuit and always produce 0 or 1
+            AstNode::Binary(op @ (BinaryOp::And | BinaryOp::Or), lhs, rhs, _) => {
+                let end = self.new_label(if *op == BinaryOp::And { "and" } else { "or" });
+
+                self.generate_expression(lhs)?;
+                self.emit("\tcmp r0, #0");
+                if *op == BinaryOp::And {
+                    self.emit(format!("\tbeq {}", end));
+                } else {
+                    self.emit("\tmovne r0, #1");
+                    self.emit(format!("\tbne {}", end));
+                }
+
+                self.generate_expression(rhs)?;
+                self.emit("\tcmp r0, #0");
+                self.emit("\tmovne r0, #1");
+                self.emit(format!("{}:", end));
+            }
+
+            AstNode::Binary(op, lhs, rhs, _) => {
+                let unsigned = self.is_unsigned(lhs, rhs);
+                self.generate_operands(lhs, rhs)?;
+
+                // Pointer arithmetic counts in elements, not bytes
+                let pointees = (self.pointee_size(lhs), self.pointee_size(rhs));
+                if matches!(op, BinaryOp::Add | BinaryOp::Sub) {
+                    match pointees {
+                        (Some(size), None) => self.emit_scale("r1", size),
+                        (None, Some(size)) => self.emit_scale("r0", size),
+                        _ => {}
+                    }
+                }
+
+                match op {
+                    BinaryOp::Add => self.emit("\tadd r0, r0, r1"),
+                    BinaryOp::Sub => {
+                        self.emit("\tsub r0, r0, r1");
+                        if let (Some(size), Some(_)) = pointees {
+                            self.emit_unscale(size);
+                        }
+                    }
+                    BinaryOp::Mul => self.emit("\tmul r0, r0, r1"),
+                    BinaryOp::Div | BinaryOp::Mod => {
+                        let routine = if unsigned {
+                            self.uses_udivmod = true;
+                            UDIVMOD_ROUTINE
+                        } else {
+                            self.uses_divmod = true;
+                            DIVMOD_ROUTINE
+                        };
+                        self.emit(format!("\tbl {}", routine));
+                        if *op == BinaryOp::Mod {
+                            self.emit("\tmov r0, r1");
+                        }
+                    }
+                    BinaryOp::BitAnd => self.emit("\tand r0, r0, r1"),
+                    BinaryOp::BitOr => self.emit("\torr r0, r0, r1"),
+                    BinaryOp::BitXor => self.emit("\teor r0, r0, r1"),
+                    BinaryOp::Shl => self.emit("\tlsl r0, r0, r1"),
+                    BinaryOp::Shr if unsigned => self.emit("\tlsr r0, r0, r1"),
+                    BinaryOp::Shr => self.emit("\tasr r0, r0, r1"),
+                    BinaryOp::Eq
+                    | BinaryOp::Ne
+                    | BinaryOp::Lt
+                    | BinaryOp::Le
+                    | BinaryOp::Gt
+                    | BinaryOp::Ge => {
+                        let cond = condition_code(*op, unsigned).unwrap();
+                        self.emit("\tcmp r0, r1");
+                        self.emit("\tmov r0, #0");
+                        self.emit(format!("\tmov{} r0, #1", cond));
+                    }
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+
+            _ => {
+                return Err(unsupported("this can't be used as a value", expr.span()).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Syscall from the table: operands are evaluated in argument order into
+    /// a reserved block on the stack, popped into r0-r6, and the number goes
+    /// into r7. What each operand must be is decided by its `ArgKind`.
+    fn generate_syscall(
+        &mut self,
+        name: &str,
+        args: &[AstNode],
+        span: Span,
+    ) -> CompileResult<()> {
+        let Some(spec) = find_syscall(name) else {
+            return Err(Diagnostic::error(
+                E_UNKNOWN_SYSCALL,
+                format!("unknown syscall `${}`", name),
+                span,
+            )
+            .into());
+        };
+        let number = get_syscall_num_or_error(self.arch, name, span)?;
+
+        // Whether the last pointer operand left its length in r1
+        let mut has_length = false;
+
+        self.reserve_arguments(spec.args.len() * 4);
+        for (index, (arg_name, kind)) in spec.args.iter().enumerate() {
+            match args.get(index) {
+                Some(arg) => match kind {
+                    ArgKind::Ptr | ArgKind::Out | ArgKind::Path => {
+                        has_length = self.generate_bytes(arg)?;
+
+                        // Park the length in the next slot so an explicit count can be
+                        // checked against it
+                        let count_follows =
+                            matches!(spec.args.get(index + 1), Some((_, ArgKind::Len)));
+                        if has_length && count_follows && self.bounds_checks {
+                            self.emit(format!("\tstr r1, [sp, #{}]", (index + 1) * 4));
+                        }
                     }
-                    Token::Identifier(id) => {
-                        let label = format!("{}_{}", self.last_fun_name, id);
-
-                        // !! Exception: Inline asm for simplicity and since we need
-                        // to load the value into a register from a label
-                        // TODO: Refactor this to use a more generic approach
-                        self.section_writer.push_text(format!(
-                            "\tmov r7, #{}\n\tldr r0, ={}\n\tldr r0, [r0]\n\tldr r1, ={}\n\tldr r2, ={}_len\n\tsvc #0\n",
-                            syscall_number, label, var, var
-                        ));
+                    ArgKind::Len if has_length && self.bounds_checks => {
+                        self.generate_expression(arg)?;
+                        self.emit(format!("\tldr r1, [sp, #{}]", index * 4));
+                        self.emit_bounds_check("r0", "r1", "hi");
                     }
-                    _ => panic!("Unsupported file descriptor type: {:?}", fd),
+                    ArgKind::Fd | ArgKind::Int | ArgKind::Len => self.generate_expression(arg)?,
+                },
+                None if has_length => self.emit("\tmov r0, r1"),
+                None => {
+                    let pointer = spec.args[index - 1].0;
+                    return Err(Diagnostic::error(
+                        E_ARGUMENT_COUNT,
+                        format!("`${}` needs an explicit `{}` here", name, arg_name),
+                        span,
+                    )
+                    .with_label(format!("`{}` is a plain address with no known length", pointer))
+                    .with_note(format!("the signature is `{}`", spec.signature()))
+                    .into());
                 }
             }
+            self.emit(format!("\tstr r0, [sp, #{}]", index * 4));
+        }
+
+        self.pop_argument_registers(spec.args.len());
+        self.emit(load_imm("r7", number as i32));
+        self.emit("\tsvc #0\n");
 
-            Token::Identifier(id) => {
-                let label = format!("{}_{}", self.last_fun_name, id);
-                let instr = syscall_3args(syscall_number, &fd_str, &label, &format!("{}_len", label));
-                self.section_writer.push_text(&instr);
+        Ok(())
+    }
+
+    /// Evaluates a pointer operand into r0. Strings, buffers and slices also
+    /// leave their length in bytes in r1, which is reported by returning true;
+    /// any other expression is taken as a plain address, e.g. 0 for NULL.
+    fn generate_bytes(&mut self, arg: &AstNode) -> CompileResult<bool> {
+        match arg {
+            AstNode::String(s, _) => {
+                let label = generate_str_varname();
+                self.section_writer.push_rodata_str_with_len(&label, s);
+                self.emit(format!("\tldr r0, ={}", label));
+                self.emit(format!("\tldr r1, ={}_len", label));
+                Ok(true)
+            }
+
+            AstNode::Variable(name, span) | AstNode::Slice(name, _, _, span) => {
+                // Pointer variables are plain addresses without a length
+                let binding = self.lookup(name, *span)?;
+                if binding.kind.is_scalar() && matches!(arg, AstNode::Variable(_, _)) {
+                    self.generate_expression(arg)?;
+                    return Ok(false);
+                }
+
+                match arg {
+                    AstNode::Slice(_, from, to, _) => {
+                        self.generate_slice(name, from.as_deref(), to.as_deref(), *span)?
+                    }
+                    _ => self.load_bytes("r0", "r1", name, *span)?,
+                }
+                Ok(true)
             }
 
-            _ => panic!("Unsupported write token: {:?}", data),
+            _ => {
+                self.generate_expression(arg)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Loads the address of `name[from..to]` into r0 and its length into r1.
+    /// A missing `from` is 0 and a missing `to` is the full length.
+    fn generate_slice(
+        &mut self,
+        name: &str,
+        from: Option<&AstNode>,
+        to: Option<&AstNode>,
+        span: Span,
+    ) -> CompileResult<()> {
+        match from {
+            Some(from) => self.generate_expression(from)?,
+            None => self.emit("\tmov r0, #0"),
+        }
+        self.push_temporary("r0");
+        if let Some(to) = to {
+            self.generate_expression(to)?;
+            self.push_temporary("r0");
+        }
+
+        self.load_bytes("r0", "r1", name, span)?;
+        if to.is_some() {
+            if self.bounds_checks {
+                self.pop_temporary("r3");
+                self.emit_bounds_check("r3", "r1", "hi");
+                self.emit("\tmov r1, r3");
+            } else {
+                self.pop_temporary("r1");
+            }
+        }
+        self.pop_temporary("r2");
+        if self.bounds_checks {
+            self.emit_bounds_check("r2", "r1", "hi");
+        }
+        self.emit("\tadd r0, r0, r2");
+        self.emit("\tsub r1, r1, r2");
+
+        Ok(())
+    }
+
+    /// Loads the address of an lvalue into r0: a variable, an element
+    /// `name[i]` or a dereferenced pointer `*p`.
+    fn generate_address(&mut self, expr: &AstNode) -> CompileResult<()> {
+        match expr {
+            AstNode::Variable(name, span) => {
+                let binding = self.lookup(name, *span)?;
+                self.load_address("r0", &binding.storage);
+            }
+            AstNode::Index(name, index, span) => {
+                self.generate_element_address(name, index, *span)?;
+            }
+            AstNode::Unary(UnaryOp::Deref, pointer, _) => self.generate_expression(pointer)?,
+            AstNode::Field(base, field, _) => {
+                self.generate_field_address(base, field)?;
+            }
+            _ => return Err(unsupported("cannot take the address of this", expr.span()).into()),
         }
-        store_syscall_return_value(&mut self.section_writer.text);
+
+        Ok(())
     }
 
-    fn generate_read(&mut self, inner: &AstNode) {
-        let (fd, buffer) = match inner {
-            AstNode::Read(fd, buffer) => (fd, buffer),
-            _ => panic!("Invalid read syscall inner node"),
+    /// Leaves the address of `base.field` in r0 and returns the field's type.
+    /// `base` is a struct, which evaluates to its address, or a pointer to one.
+    fn generate_field_address(&mut self, base: &AstNode, field: &str) -> CompileResult<Type> {
+        let def = match self.types.expression(base).map(Type::decay) {
+            Some(Type::Ptr(target)) => match *target {
+                Type::Struct(def) => def,
+                _ => return Err(unsupported("not a struct", base.span()).into()),
+            },
+            _ => return Err(unsupported("not a struct", base.span()).into()),
+        };
+        let Some(field) = def.field(field) else {
+            return Err(unsupported(format!("no field `{}`", field), base.span()).into());
         };
 
-        let syscall_number = get_syscall_num_or_panic(self.arch, "read");
-        let fd_str = fd.to_string();
-        let label = format!("{}_{}", self.last_fun_name, buffer);
+        self.generate_expression(base)?;
+        match field.offset {
+            0 => {}
+            offset if is_immediate(offset as u32) => {
+                self.emit(format!("\tadd r0, r0, #{}", offset))
+            }
+            offset => {
+                self.emit(load_imm("r1", offset));
+                self.emit("\tadd r0, r0, r1");
+            }
+        }
+
+        Ok(field.ty.clone())
+    }
+
+    /// Reads a value of type `ty` from the address in r0. Arrays and structs
+    /// are used through their address, so that is left as it is.
+    fn emit_load(&mut self, ty: &Type) {
+        if !ty.is_aggregate() {
+            self.emit(format!("\t{} r0, [r0]", load_instruction(ty)));
+        }
+    }
 
-        let instr = syscall_2args(syscall_number, &fd_str, &label);
-        self.section_writer.push_text(&instr);
+    /// Size of what `expr` points to, if it is a pointer (or a string or
+    /// buffer, which stand for a pointer to their first element).
+    fn pointee_size(&self, expr: &AstNode) -> Option<i32> {
+        match self.types.expression(expr).map(Type::decay) {
+            Some(Type::Ptr(target)) => Some(target.size()),
+            _ => None,
+        }
+    }
+
+    /// Multiplies `reg` by an element size for pointer arithmetic.
+    fn emit_scale(&mut self, reg: &str, size: i32) {
+        match size {
+            1 => {}
+            n if n.count_ones() == 1 => {
+                self.emit(format!("\tlsl {}, {}, #{}", reg, reg, n.trailing_zeros()))
+            }
+            n => {
+                self.emit(load_imm("r3", n));
+                self.emit(format!("\tmul {}, {}, r3", reg, reg));
+            }
+        }
+    }
 
-        store_syscall_return_value(&mut self.section_writer.text);
+    /// Divides a byte distance in r0 by an element size, for `p - q`.
+    fn emit_unscale(&mut self, size: i32) {
+        match size {
+            1 => {}
+            n if n.count_ones() == 1 => self.emit(format!("\tasr r0, r0, #{}", n.trailing_zeros())),
+            n => {
+                self.uses_divmod = true;
+                self.emit(load_imm("r1", n));
+                self.emit(format!("\tbl {}", DIVMOD_ROUTINE));
+            }
+        }
     }
 
-    fn generate_exit(&mut self, inner: &AstNode) {
-        let code = match inner {
-            AstNode::Exit(token) => token,
-            _ => panic!("Invalid exit syscall inner node"),
+    /// Leaves the address of `name[index]` in r0 and returns the type of the
+    /// element. Strings are indexed byte by byte.
+    fn generate_element_address(
+        &mut self,
+        name: &str,
+        index: &AstNode,
+        span: Span,
+    ) -> CompileResult<Type> {
+        let binding = self.lookup(name, span)?;
+        let (element, count) = match binding.ty {
+            Type::Array(element, count) => (*element, Some(count)),
+            _ => (Type::U8, None),
         };
 
-        let syscall_number = get_syscall_num_or_panic(self.arch, "exit");
+        self.generate_expression(index)?;
+        self.push_temporary("r0");
+        self.load_bytes("r0", "r1", name, span)?;
+        self.pop_temporary("r2");
 
-        let asm = match code {
-            Token::Number(n) => syscall_1arg(syscall_number, &n.to_string()),
-            Token::Identifier(id) => syscall_1arg(syscall_number, &id),
-            _ => panic!("Unsupported exit code: {:?}", code),
+        if self.bounds_checks {
+            // r1 holds the length in bytes, which is the count for bytes only
+            if let Some(count) = count
+                && element.size() != 1
+            {
+                self.emit(load_imm("r1", count));
+            }
+            self.emit_bounds_check("r2", "r1", "hs");
+        }
+
+        match element.size() {
+            1 => self.emit("\tadd r0, r0, r2"),
+            n if n.count_ones() == 1 => {
+                self.emit(format!("\tadd r0, r0, r2, lsl #{}", n.trailing_zeros()))
+            }
+            n => {
+                self.emit_scale("r2", n);
+                self.emit("\tadd r0, r0, r2");
+            }
+        }
+
+        Ok(element)
+    }
+
+    /// Compares `index` with `limit` and jumps to the panic handler if the
+    /// condition `failure` holds: `hi` for an end that may equal the limit,
+    /// `hs` for an element index. Comparisons are unsigned, so negative
+    /// indices fail as well.
+    fn emit_bounds_check(&mut self, index: &str, limit: &str, failure: &str) {
+        self.uses_bounds_panic = true;
+        self.emit(format!("\tcmp {}, {}", index, limit));
+        self.emit(format!("\tb{} {}", failure, BOUNDS_PANIC_ROUTINE));
+    }
+
+    /// `$syscall(nr, args...)`: operands are evaluated left to right into a
+    /// reserved block on the stack, then popped into r0-r6 and r7. The
+    /// kernel's result is left in r0.
+    fn generate_raw_syscall(&mut self, inner: &AstNode) -> CompileResult<()> {
+        let (number, args) = match inner {
+            AstNode::RawSyscall(number, args, _) => (number, args),
+            _ => return Err(unsupported("invalid raw syscall node", inner.span()).into()),
         };
 
-        self.section_writer.push_text(&asm);
+        // The syscall number goes in the slot above the arguments
+        self.reserve_arguments((args.len() + 1) * 4);
+        self.generate_expression(number)?;
+        self.emit(format!("\tstr r0, [sp, #{}]", args.len() * 4));
+        for (index, arg) in args.iter().enumerate() {
+            self.generate_expression(arg)?;
+            self.emit(format!("\tstr r0, [sp, #{}]", index * 4));
+        }
+
+        self.pop_argument_registers(args.len());
+        self.pop_temporary("r7");
+        self.emit("\tsvc #0\n");
+
+        Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extra::diagnostic::E_MISMATCHED_TYPES,
+        frontend::{checker::check, parser::parse, resolver::resolve, tokenizer::tokenize},
+    };
 
-    fn generate_open(&mut self, inner: &AstNode) {
-        let (path, flags, mode) = match inner {
-            AstNode::Open(path, flags, mode) => (path, flags, mode),
-            _ => panic!("Invalid open syscall inner node"),
+    /// Code of the error reported while checking or generating `source`, if any.
+    fn error(source: &str) -> Option<&'static str> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let resolution = resolve(&program).unwrap();
+        let types = match check(&program, &resolution) {
+            Ok(types) => types,
+            Err(diagnostics) => return Some(diagnostics[0].code),
         };
+        let generator = generate(&program, resolution, types, Architecture::Arm32, false);
+        generator.err().map(|d| d.code)
+    }
+
+    /// Assembly emitted into .text for `source`.
+    fn text(source: &str) -> String {
+        text_with(source, false)
+    }
+
+    fn text_with(source: &str, bounds_checks: bool) -> String {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let resolution = resolve(&program).unwrap();
+        let types = check(&program, &resolution).unwrap();
+        let generator =
+            generate(&program, resolution, types, Architecture::Arm32, bounds_checks).unwrap();
+        generator.section_writer.text.join("\n")
+    }
+
+    fn calls(text: &str, routine: &str) -> usize {
+        text.matches(&format!("\tbl {}\n", routine)).count()
+    }
+
+    #[test]
+    fn comparisons_branch_on_the_inverse_condition() {
+        let text = text("fn main() { let a = 1; if a < 2 { $exit(1); } else if a >= 3 { } }");
+        assert!(text.contains("\tcmp r0, r1\n\tbge .L_start_else_"), "{}", text);
+        assert!(text.contains("\tcmp r0, r1\n\tblt .L_start_else_"), "{}", text);
+    }
+
+    #[test]
+    fn other_conditions_compare_against_zero() {
+        let text = text("fn main() { let a = 1; if a & 1 { $exit(1); } }");
+        assert!(text.contains("\tcmp r0, #0\n\tbeq .L_start_else_"), "{}", text);
+    }
+
+    /// Name of the first label defined in `text` that starts with `prefix`.
+    fn label<'a>(text: &'a str, prefix: &str) -> &'a str {
+        text.lines()
+            .find_map(|line| line.strip_suffix(':').filter(|l| l.starts_with(prefix)))
+            .unwrap()
+    }
+
+    #[test]
+    fn break_and_continue_target_the_innermost_loop() {
+        let text = text("fn main() { loop { while 1 { break; } continue; } }");
+        let (outer, outer_end) =
+            (label(&text, ".L_start_loop_"), label(&text, ".L_start_endloop_"));
+        let (inner, inner_end) =
+            (label(&text, ".L_start_while_"), label(&text, ".L_start_endwhile_"));
+
+        assert!(text.contains(&format!("\tb {}\n\tb {}\n", inner_end, inner)), "{}", text);
+        let continue_outer = format!("\tb {}\n\tb {}\n{}:", outer, outer, outer_end);
+        assert!(text.contains(&continue_outer), "{}", text);
+    }
+
+    #[test]
+    fn break_outside_of_a_loop() {
+        assert_eq!(error("fn main() { break; }"), Some(E_OUTSIDE_LOOP));
+        assert_eq!(error("fn main() { if 1 { continue; } }"), Some(E_OUTSIDE_LOOP));
+    }
+
+    #[test]
+    fn calls_are_checked_against_the_definition() {
+        let source = "fn main() { let x = add(1, 2); } fn add(a: i32, b: i32) { return a + b; }";
+        assert_eq!(error(source), None);
+        assert_eq!(error("fn main() { add(1); } fn add(a: i32, b: i32) {}"), Some(E_ARGUMENT_COUNT));
+    }
+
+    #[test]
+    fn arguments_past_the_fourth_go_on_the_stack() {
+        let source = "fn f(a: i32, b: i32, c: i32, d: i32, e: i32, g: i32) {} \
+                      fn main() { f(1, 2, 3, 4, 5, 6); }";
+        let text = text(source);
+        assert!(text.contains("\tsub sp, sp, #24"), "{}", text);
+        assert!(text.contains("\tpop {r0-r3}\n\tbl f\n\tadd sp, sp, #8"), "{}", text);
+    }
+
+    #[test]
+    fn raw_syscall_number_goes_in_r7() {
+        let text = text("fn main() { let n = 4; $syscall(n, 1, n + 1); }");
+        assert!(text.contains("\tsub sp, sp, #12"), "{}", text);
+        assert!(text.contains("\tpop {r0, r1}\n\tpop {r7}\n\tsvc #0"), "{}", text);
+    }
 
-        let syscall_number = get_syscall_num_or_panic(self.arch, "open");
+    #[test]
+    fn syscall_arguments_are_checked_against_the_table() {
+        assert_eq!(error("fn main() { buf[4] b; $read(0, b); }"), None);
+        assert_eq!(error("fn main() { $read(0, \"abc\"); }"), Some(E_MISMATCHED_TYPES));
+    }
 
-        let var = generate_str_varname();
-        self.section_writer.push_rodata_str_with_len(&var, path);
+    #[test]
+    fn syscall_argument_kinds() {
+        assert_eq!(error("fn main() { buf[8] fds; $pipe(fds); $write(1, 0, 0); }"), None);
+        assert_eq!(error("fn main() { $close(\"abc\"); }"), Some(E_MISMATCHED_TYPES));
+        assert_eq!(error("fn main() { buf[4] b; $close(b); }"), Some(E_MISMATCHED_TYPES));
+        assert_eq!(error("fn main() { let s = \"a\"; $pipe(s); }"), Some(E_MISMATCHED_TYPES));
+    }
 
-        let flags_str = flags.to_string();
-        let mode_str = mode.to_string();
+    #[test]
+    fn slices_offset_the_address_and_shorten_the_length() {
+        let text = text("fn main() { buf[8] b; $write(1, b[2..5]); $write(1, b[3..]); }");
+        let (bounded, open) = text.split_at(text.find("mov r0, #3").unwrap());
 
-        let instr = syscall_3args(syscall_number, &var, &flags_str, &mode_str);
-        self.section_writer.push_text(&instr);
+        let trim = "\tpop {r2}\n\tadd r0, r0, r2\n\tsub r1, r1, r2\n";
+        assert!(bounded.contains(&format!("\tmov r1, #8\n\tpop {{r1}}\n{}", trim)));
+        assert!(open.contains(&format!("\tmov r1, #8\n{}", trim)));
+    }
 
-        store_syscall_return_value(&mut self.section_writer.text);
+    #[test]
+    fn explicit_lengths_replace_the_derived_one() {
+        let text = text("fn main() { buf[8] b; $write(1, b, 4); }");
+        assert!(text.contains("\tmov r0, #4\n\tstr r0, [sp, #8]"), "{}", text);
+        assert_eq!(error("fn main() { buf[8] b; let n = b[1..] + 1; }"), Some(E_MISMATCHED_TYPES));
+    }
+
+    #[test]
+    fn syscalls_are_expressions() {
+        let text = text("fn main() { let pid = $getpid() + 1; $exit($getpid()); }");
+        assert!(text.contains("\tmov r7, #20\n\tsvc #0\n\n\tpush {r0}"), "{}", text);
+        assert!(!text.contains("syscall_ret_val"));
+        assert_eq!(error("fn main() { let x = $exit(0); }"), Some(E_UNSUPPORTED));
+    }
+
+    #[test]
+    fn plain_addresses_need_an_explicit_length() {
+        assert_eq!(error("fn main() { buf[8] b; let p = b; $write(1, p + 4, 4); }"), None);
+        assert_eq!(error("fn main() { buf[8] b; let p = b; $write(1, p); }"), Some(E_ARGUMENT_COUNT));
+    }
+
+    #[test]
+    fn scalars_load_their_value_and_buffers_their_address() {
+        let text = text("fn main() { let x = 7; buf[4] b; let p = b; $exit(x); }");
+        assert!(text.contains("\tsub r0, fp, #12\n\tstr r0, [fp, #-8]"), "{}", text);
+        assert!(text.contains("\tldr r0, [fp, #-4]\n\tstr r0, [sp, #0]"), "{}", text);
+    }
+
+    #[test]
+    fn u32_division_is_unsigned() {
+        let text = text(
+            "fn f(a: u32, b: u32) -> u32 { return a / b + a % b; } \
+             fn g(a: u32, b: i32) -> u32 { return a / b; } \
+             fn main() { let big = 3000000000; $exit(f(7, 2) + big / 2); }",
+        );
+        assert_eq!(calls(&text, UDIVMOD_ROUTINE), 4);
+        assert_eq!(calls(&text, DIVMOD_ROUTINE), 0);
+    }
+
+    #[test]
+    fn i32_division_is_signed() {
+        let text = text("fn f(a: i32, b: i32) -> i32 { return a / b + a % b; } fn main() {}");
+        assert_eq!(calls(&text, DIVMOD_ROUTINE), 2);
+        assert_eq!(calls(&text, UDIVMOD_ROUTINE), 0);
+    }
+
+    #[test]
+    fn bounds_checks_only_with_the_flag() {
+        let source = "fn main() { buf[8] b; let n = $read(0, b); $write(1, b[1..n]); }";
+        let check = format!("\tbhi {}\n", BOUNDS_PANIC_ROUTINE);
+        assert!(!text(source).contains(&check));
+
+        let text = text_with(source, true);
+        assert_eq!(text.matches(&check).count(), 2);
+        assert!(text.contains(&format!("{}:", BOUNDS_PANIC_ROUTINE)));
+    }
+    #[test]
+    fn elements_are_scaled_and_sized_by_their_type() {
+        let text = text("fn main() { buf[4] w: u16; buf[4] b: i8; w[1] = b[2]; }");
+        assert!(text.contains("\tadd r0, r0, r2\n\tldrsb r0, [r0]"), "{}", text);
+        assert!(text.contains("\tadd r0, r0, r2, lsl #1\n\tpush {r0}"), "{}", text);
+        assert!(text.contains("\tpop {r1}\n\tstrh r0, [r1]"), "{}", text);
+    }
+    #[test]
+    fn pointer_arithmetic_counts_elements() {
+        let text = text("fn main() { buf[4] w: u32; let p = &w[0]; let q = p + 2; \
+                         let n: i32 = q - p; }");
+        assert!(text.contains("\tpop {r0}\n\tlsl r1, r1, #2\n\tadd r0, r0, r1"), "{}", text);
+        assert!(text.contains("\tsub r0, r0, r1\n\tasr r0, r0, #2"), "{}", text);
     }
 }
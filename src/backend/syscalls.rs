@@ -1,113 +1,408 @@
 use crate::{
+    extra::diagnostic::{CompileResult, Diagnostic, E_ARGUMENT_COUNT},
     frontend::parser::{AstNode, Parser},
     frontend::tokenizer::Token,
 };
 
-pub fn parse_sys_write(parser: &mut Parser) -> AstNode {
-    parser.consume(Token::Syscall("write".to_string()));
+/// What a syscall argument means, which decides what the caller may pass
+/// and how the value ends up in its register.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgKind {
+    Fd,   // file descriptor
+    Ptr,  // address of a string or buffer, or 0 for NULL
+    Out,  // address of a buffer the kernel writes into, or 0 for NULL
+    Len,  // byte count, may be left out right after a `Ptr` or `Out`
+    Int,  // plain number such as flags or a mode
+    Path, // address of a NUL-terminated string
+}
 
-    parser.consume(Token::ParentOpen);
+/// What the kernel hands back in r0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReturnKind {
+    Never, // the syscall does not return
+    Fd,    // new file descriptor or a negative errno
+    Size,  // byte count or a negative errno
+    Int,   // 0 or a plain value on success, a negative errno on failure
+    Ptr,   // an address, or a negative errno
+}
 
-    let fd = match parser.current_token() {
-        Token::Number(n) => {
-            parser.consume(Token::Number(n));
-            Token::Number(n)
-        },
-        Token::Identifier(id) => {
-            parser.consume(Token::Identifier(id.clone()));
-            Token::Identifier(id)
-        },
-        _ => panic!("Expected file descriptor (number)"),
-    };
+/// Syscall numbers per architecture; `None` where the syscall doesn't exist.
+#[derive(Debug, Clone, Copy)]
+pub struct SyscallNumbers {
+    pub arm32: Option<u32>,
+    pub arm64: Option<u32>,
+    pub x86: Option<u32>,
+    pub x86_64: Option<u32>,
+}
 
-    parser.consume(Token::Comma);
+const fn numbers(arm32: u32, arm64: u32, x86: u32, x86_64: u32) -> SyscallNumbers {
+    SyscallNumbers {
+        arm32: Some(arm32),
+        arm64: Some(arm64),
+        x86: Some(x86),
+        x86_64: Some(x86_64),
+    }
+}
+
+/// Declarative description of a `$name(...)` wrapper. Parsing, argument
+/// checks and code generation are all derived from it.
+#[derive(Debug)]
+pub struct SyscallSpec {
+    pub name: &'static str,
+    pub numbers: SyscallNumbers,
+    pub args: &'static [(&'static str, ArgKind)],
+    pub returns: ReturnKind,
+}
+
+impl SyscallSpec {
+    /// Arguments the caller has to spell out; a trailing length right after a
+    /// pointer is derived from the string or buffer when left out.
+    pub fn required_args(&self) -> usize {
+        match self.args {
+            [.., (_, ArgKind::Ptr | ArgKind::Out), (_, ArgKind::Len)] => self.args.len() - 1,
+            _ => self.args.len(),
+        }
+    }
+
+    /// Human readable signature, e.g. `$write(fd, buf, count)`.
+    pub fn signature(&self) -> String {
+        let args: Vec<&str> = self.args.iter().map(|(name, _)| *name).collect();
+        format!("${}({})", self.name, args.join(", "))
+    }
+}
 
-    let write_data = match parser.current_token() {
-        Token::String(s) => {
-            parser.consume(Token::String(s.clone()));
-            Token::String(s)
+use ArgKind::{Fd, Int, Len, Out, Path, Ptr};
+
+pub const SYSCALLS: &[SyscallSpec] = &[
+    SyscallSpec {
+        name: "exit",
+        numbers: numbers(1, 93, 1, 60),
+        args: &[("status", Int)],
+        returns: ReturnKind::Never,
+    },
+    SyscallSpec {
+        name: "fork",
+        // arm64 only has `clone`
+        numbers: SyscallNumbers {
+            arm32: Some(2),
+            arm64: None,
+            x86: Some(2),
+            x86_64: Some(57),
         },
-        Token::Identifier(id) => {
-            parser.consume(Token::Identifier(id.clone()));
-            Token::Identifier(id)
+        args: &[],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "read",
+        numbers: numbers(3, 63, 3, 0),
+        args: &[("fd", Fd), ("buf", Out), ("count", Len)],
+        returns: ReturnKind::Size,
+    },
+    SyscallSpec {
+        name: "write",
+        numbers: numbers(4, 64, 4, 1),
+        args: &[("fd", Fd), ("buf", Ptr), ("count", Len)],
+        returns: ReturnKind::Size,
+    },
+    SyscallSpec {
+        name: "open",
+        // arm64 only has `openat`
+        numbers: SyscallNumbers {
+            arm32: Some(5),
+            arm64: None,
+            x86: Some(5),
+            x86_64: Some(2),
         },
-        _ => panic!("Expected write data (string or identifier)"),
-    };
-
-    parser.consume(Token::ParentClose);
+        args: &[("path", Path), ("flags", Int), ("mode", Int)],
+        returns: ReturnKind::Fd,
+    },
+    SyscallSpec {
+        name: "close",
+        numbers: numbers(6, 57, 6, 3),
+        args: &[("fd", Fd)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "execve",
+        numbers: numbers(11, 221, 11, 59),
+        args: &[("path", Path), ("argv", Int), ("envp", Int)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "lseek",
+        numbers: numbers(19, 62, 19, 8),
+        args: &[("fd", Fd), ("offset", Int), ("whence", Int)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "getpid",
+        numbers: numbers(20, 172, 20, 39),
+        args: &[],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "kill",
+        numbers: numbers(37, 129, 37, 62),
+        args: &[("pid", Int), ("sig", Int)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "pipe",
+        // arm64 only has `pipe2`
+        numbers: SyscallNumbers {
+            arm32: Some(42),
+            arm64: None,
+            x86: Some(42),
+            x86_64: Some(22),
+        },
+        args: &[("fds", Out)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "brk",
+        numbers: numbers(45, 214, 45, 12),
+        args: &[("addr", Int)],
+        returns: ReturnKind::Ptr,
+    },
+    SyscallSpec {
+        name: "ioctl",
+        numbers: numbers(54, 29, 54, 16),
+        args: &[("fd", Fd), ("request", Int), ("arg", Int)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "dup2",
+        // arm64 only has `dup3`
+        numbers: SyscallNumbers {
+            arm32: Some(63),
+            arm64: None,
+            x86: Some(63),
+            x86_64: Some(33),
+        },
+        args: &[("oldfd", Fd), ("newfd", Fd)],
+        returns: ReturnKind::Fd,
+    },
+    SyscallSpec {
+        name: "munmap",
+        numbers: numbers(91, 215, 91, 11),
+        args: &[("addr", Int), ("length", Int)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "wait4",
+        numbers: numbers(114, 260, 114, 61),
+        args: &[("pid", Int), ("wstatus", Out), ("options", Int), ("rusage", Out)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "nanosleep",
+        numbers: numbers(162, 101, 162, 35),
+        args: &[("req", Ptr), ("rem", Out)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "mmap2",
+        // 64-bit targets have `mmap` with a byte offset instead
+        numbers: SyscallNumbers {
+            arm32: Some(192),
+            arm64: None,
+            x86: Some(192),
+            x86_64: None,
+        },
+        args: &[
+            ("addr", Int),
+            ("length", Int),
+            ("prot", Int),
+            ("flags", Int),
+            ("fd", Fd),
+            ("pgoffset", Int),
+        ],
+        returns: ReturnKind::Ptr,
+    },
+    SyscallSpec {
+        name: "stat64",
+        // 64-bit targets use `stat`/`newfstatat` with a different layout
+        numbers: SyscallNumbers {
+            arm32: Some(195),
+            arm64: None,
+            x86: Some(195),
+            x86_64: None,
+        },
+        args: &[("path", Path), ("statbuf", Out)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "getdents64",
+        numbers: numbers(217, 61, 220, 217),
+        args: &[("fd", Fd), ("dirp", Out), ("count", Len)],
+        returns: ReturnKind::Size,
+    },
+    SyscallSpec {
+        name: "clock_gettime",
+        numbers: numbers(263, 113, 265, 228),
+        args: &[("clockid", Int), ("tp", Out)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "socket",
+        numbers: numbers(281, 198, 359, 41),
+        args: &[("domain", Int), ("type", Int), ("protocol", Int)],
+        returns: ReturnKind::Fd,
+    },
+    SyscallSpec {
+        name: "bind",
+        numbers: numbers(282, 200, 361, 49),
+        args: &[("sockfd", Fd), ("addr", Ptr), ("addrlen", Len)],
+        returns: ReturnKind::Int,
+    },
+    SyscallSpec {
+        name: "connect",
+        numbers: numbers(283, 203, 362, 42),
+        args: &[("sockfd", Fd), ("addr", Ptr), ("addrlen", Len)],
+        returns: ReturnKind::Int,
+    },
+];
 
-    AstNode::Write(fd, write_data)
+pub fn find_syscall(name: &str) -> Option<&'static SyscallSpec> {
+    SYSCALLS.iter().find(|spec| spec.name == name)
 }
 
-pub fn parse_sys_read(parser: &mut Parser) -> AstNode {
-    parser.consume(Token::Syscall("read".to_string()));
+/// `$name(arg, ...)` for any syscall in the table. Every argument is parsed
+/// as an expression; what it has to evaluate to is checked by the type checker.
+pub fn parse_sys_table(parser: &mut Parser, spec: &SyscallSpec) -> CompileResult<AstNode> {
+    let start = parser.current_span();
+    parser.consume(Token::Syscall(spec.name.to_string()))?;
+
+    parser.consume(Token::ParentOpen)?;
+
+    let mut args = Vec::new();
+    while parser.current_token() != Token::ParentClose {
+        args.push(parser.parse_expression()?);
 
-    parser.consume(Token::ParentOpen);
+        if parser.current_token() != Token::ParentClose {
+            parser.consume(Token::Comma)?;
+        }
+    }
+    parser.consume(Token::ParentClose)?;
 
-    let fd = match parser.current_token() {
-        Token::Number(n) => n,
-        _ => panic!("Expected file descriptor (number)"),
-    };
-    parser.consume(Token::Number(fd));
+    let span = parser.span_from(start);
 
-    parser.consume(Token::Comma);
+    let required = spec.required_args();
+    if args.len() < required || args.len() > spec.args.len() {
+        let expected = if required == spec.args.len() {
+            required.to_string()
+        } else {
+            format!("{} or {}", required, spec.args.len())
+        };
 
-    let buffer = match parser.current_token() {
-        Token::Identifier(id) => id,
-        _ => panic!("Expected buffer identifier"),
-    };
+        let diagnostic = Diagnostic::error(
+            E_ARGUMENT_COUNT,
+            format!(
+                "`${}` takes {} argument(s) but {} were supplied",
+                spec.name,
+                expected,
+                args.len()
+            ),
+            span,
+        )
+        .with_label(format!("expected {} argument(s)", expected))
+        .with_note(format!("the signature is `{}`", spec.signature()));
 
-    parser.consume(Token::Identifier(buffer.clone()));
-    parser.consume(Token::ParentClose);
+        let diagnostic = if required < spec.args.len() {
+            diagnostic.with_note("the length may be left out, it's taken from the string or buffer")
+        } else {
+            diagnostic
+        };
+        return Err(diagnostic.into());
+    }
 
-    AstNode::Read(fd as usize, buffer)
+    Ok(AstNode::Syscall(spec.name.to_string(), args, span))
 }
 
-pub fn parse_sys_exit(parser: &mut Parser) -> AstNode {
-    parser.consume(Token::Syscall("exit".to_string()));
+/// Registers r0-r6 carry syscall arguments on arm32 EABI.
+pub const MAX_SYSCALL_ARGS: usize = 7;
 
-    parser.consume(Token::ParentOpen);
+/// `$syscall(number, arg0, ..., arg6)`: every operand is an expression.
+pub fn parse_sys_raw(parser: &mut Parser) -> CompileResult<AstNode> {
+    let start = parser.current_span();
+    parser.consume(Token::Syscall("syscall".to_string()))?;
 
-    let code = match parser.current_token() {
-        Token::Number(n) => Token::Number(n),
-        Token::Identifier(id) => Token::Identifier(id),
-        _ => panic!("Expected exit code (number or identifier)"),
-    };
-    parser.consume(code.clone());
+    parser.consume(Token::ParentOpen)?;
 
-    parser.consume(Token::ParentClose);
+    if parser.current_token() == Token::ParentClose {
+        return Err(parser.unexpected("a syscall number").into());
+    }
+    let number = parser.parse_expression()?;
 
-    AstNode::Exit(code)
-}
+    let mut args = Vec::new();
+    while parser.current_token() == Token::Comma {
+        parser.consume(Token::Comma)?;
 
-pub fn parse_sys_open(parser: &mut Parser) -> AstNode {
-    parser.consume(Token::Syscall("open".to_string()));
+        let arg = parser.parse_expression()?;
+        if args.len() == MAX_SYSCALL_ARGS {
+            return Err(Diagnostic::error(
+                E_ARGUMENT_COUNT,
+                format!("syscalls take at most {} arguments", MAX_SYSCALL_ARGS),
+                arg.span(),
+            )
+            .with_label("no register left for this argument")
+            .with_note("arguments are passed in r0-r6 and the number in r7")
+            .into());
+        }
+        args.push(arg);
+    }
 
-    parser.consume(Token::ParentOpen);
+    parser.consume(Token::ParentClose)?;
 
-    let filename = match parser.current_token() {
-        Token::String(s) => s,
-        _ => panic!("Expected filename (string)"),
-    };
-    parser.consume(Token::String(filename.clone()));
+    Ok(AstNode::RawSyscall(
+        Box::new(number),
+        args,
+        parser.span_from(start),
+    ))
+}
 
-    parser.consume(Token::Comma);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::{parser::parse, tokenizer::tokenize};
 
-    let flags = match parser.current_token() {
-        Token::Number(n) => n,
-        _ => panic!("Expected flags (number)"),
-    };
-    parser.consume(Token::Number(flags));
+    /// Codes of the syntax errors reported for `source`, in order.
+    fn codes(source: &str) -> Vec<&'static str> {
+        match parse(tokenize(source).unwrap()) {
+            Ok(_) => Vec::new(),
+            Err(diagnostics) => diagnostics.iter().map(|d| d.code).collect(),
+        }
+    }
 
-    parser.consume(Token::Comma);
+    #[test]
+    fn table_is_well_formed() {
+        for (index, spec) in SYSCALLS.iter().enumerate() {
+            assert!(SYSCALLS[..index].iter().all(|other| other.name != spec.name));
+            assert!(spec.args.len() <= MAX_SYSCALL_ARGS, "{}", spec.name);
 
-    let mode = match parser.current_token() {
-        Token::Number(n) => n,
-        _ => panic!("Expected mode (number)"),
-    };
-    parser.consume(Token::Number(mode));
+            // A length always describes the pointer right before it
+            for (position, (_, kind)) in spec.args.iter().enumerate() {
+                if *kind == ArgKind::Len {
+                    let (_, previous) = spec.args[position - 1];
+                    assert!(matches!(previous, ArgKind::Ptr | ArgKind::Out), "{}", spec.name);
+                }
+            }
+        }
+    }
 
-    parser.consume(Token::ParentClose);
+    #[test]
+    fn trailing_lengths_are_optional() {
+        let write = find_syscall("write").unwrap();
+        assert_eq!(write.required_args(), 2);
+        assert_eq!(write.signature(), "$write(fd, buf, count)");
+        assert_eq!(find_syscall("open").unwrap().required_args(), 3);
+        assert!(find_syscall("syscall").is_none());
+    }
 
-    AstNode::Open(filename, flags as usize, mode as usize)
+    #[test]
+    fn argument_counts_are_checked() {
+        let source = "fn main() { $write(1, \"a\"); $write(1, \"a\", 1); $write(1); \
+                      $write(1, \"a\", 1, 2); $open(\"file\", 0); $exit(); }";
+        assert_eq!(codes(source), [E_ARGUMENT_COUNT; 4]);
+    }
 }
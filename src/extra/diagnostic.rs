@@ -0,0 +1,100 @@
+use crate::frontend::span::Span;
+
+// ====== ERROR CODES ======
+
+pub const E_UNKNOWN_CHARACTER: &str = "E0001";
+pub const E_INVALID_LITERAL: &str = "E0002";
+pub const E_UNEXPECTED_TOKEN: &str = "E0003";
+pub const E_UNKNOWN_SYSCALL: &str = "E0004";
+pub const E_UNSUPPORTED: &str = "E0005";
+pub const E_UNAVAILABLE_SYSCALL: &str = "E0006";
+pub const E_UNTERMINATED_COMMENT: &str = "E0007";
+pub const E_IMMUTABLE_ASSIGNMENT: &str = "E0008";
+pub const E_UNKNOWN_VARIABLE: &str = "E0009";
+pub const E_OUTSIDE_LOOP: &str = "E0010";
+pub const E_UNKNOWN_FUNCTION: &str = "E0011";
+pub const E_ARGUMENT_COUNT: &str = "E0012";
+pub const E_DUPLICATE_DEFINITION: &str = "E0013";
+pub const E_MISMATCHED_TYPES: &str = "E0014";
+pub const E_OUT_OF_BOUNDS: &str = "E0015";
+pub const E_UNKNOWN_FIELD: &str = "E0016";
+pub const E_NOT_CONSTANT: &str = "E0017";
+
+// ====== WARNING CODES ======
+
+pub const W_SHADOWED_NAME: &str = "W0001";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+pub type CompileResult<T> = Result<T, Box<Diagnostic>>;
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            primary: Label {
+                span,
+                message: String::new(),
+            },
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, message, span)
+        }
+    }
+
+    /// Text printed next to the carets under the primary span.
+    pub fn with_label(mut self, message: impl Into<String>) -> Self {
+        self.primary.message = message.into();
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
@@ -1,2 +1,3 @@
 pub mod utils;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod diagnostic;
\ No newline at end of file
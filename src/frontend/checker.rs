@@ -0,0 +1,1080 @@
+use std::collections::HashMap;
+
+use crate::{
+    backend::syscalls::{ArgKind, ReturnKind, SyscallSpec, find_syscall},
+    extra::diagnostic::{Diagnostic, E_INVALID_LITERAL, E_MISMATCHED_TYPES, E_OUT_OF_BOUNDS},
+    frontend::{
+        consteval::evaluate,
+        parser::{AstNode, BinaryOp, UnaryOp, unknown_field},
+        resolver::{Resolution, SymbolId},
+        span::Span,
+        types::{MAX_ALIGN, Type},
+    },
+};
+
+/// Parameter and result types of a function.
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub returns: Type,
+}
+
+/// Output of the type checker: the type of every symbol and expression and
+/// the signature of every function. Expressions are keyed by their span.
+#[derive(Debug, Default)]
+pub struct TypeTable {
+    symbols: HashMap<SymbolId, Type>,
+    signatures: HashMap<SymbolId, Signature>,
+    expressions: HashMap<(usize, usize), Type>,
+}
+
+impl TypeTable {
+    pub fn symbol(&self, id: SymbolId) -> Option<&Type> {
+        self.symbols.get(&id)
+    }
+
+    pub fn signature(&self, id: SymbolId) -> Option<&Signature> {
+        self.signatures.get(&id)
+    }
+
+    pub fn expression(&self, expr: &AstNode) -> Option<&Type> {
+        let span = expr.span();
+        self.expressions.get(&(span.start, span.end))
+    }
+}
+
+/// Infers the type of every `let` without an annotation and checks that
+/// values match the variables, parameters, returns and syscall arguments
+/// they flow into. Integers convert into each other freely; everything
+/// else has to match.
+pub fn check(program: &AstNode, resolution: &Resolution) -> Result<TypeTable, Vec<Diagnostic>> {
+    let mut checker = Checker {
+        resolution,
+        table: TypeTable::default(),
+        returns: Type::I32,
+        errors: Vec::new(),
+    };
+    checker.check_statement(program);
+
+    if checker.errors.is_empty() {
+        Ok(checker.table)
+    } else {
+        Err(checker.errors)
+    }
+}
+
+/// Whether a value of type `from` may be stored where `to` is expected.
+fn assignable(from: &Type, to: &Type) -> bool {
+    match (from, to) {
+        _ if from == to => true,
+        (_, to) if to.is_integer() => from.is_integer() || *from == Type::Bool,
+        // `*u8` is the untyped pointer: any pointer converts to and from it.
+        // Strings are read-only, so they never turn into one.
+        (Type::Array(_, _) | Type::Ptr(_), Type::Ptr(target)) => {
+            let from = from.decay();
+            from == *to || **target == Type::U8 || from == Type::Ptr(Box::new(Type::U8))
+        }
+        _ => false,
+    }
+}
+
+/// A string used where it would turn into a writable pointer.
+fn read_only_string(span: Span, message: impl Into<String>) -> Diagnostic {
+    Diagnostic::error(E_MISMATCHED_TYPES, message, span)
+        .with_label("strings are read-only")
+        .with_note("read single bytes with `name[i]` or pass the string to a syscall")
+}
+
+/// `value` stored in a `str` variable, which only a string literal can be.
+fn not_a_string_literal(value: &AstNode) -> Diagnostic {
+    Diagnostic::error(E_MISMATCHED_TYPES, "`str` variables need a string literal", value.span())
+        .with_label("not a string literal")
+        .with_note("strings are read-only, so they can't be copied or turned into a `*u8`")
+}
+
+/// Whether `ty` can be used in arithmetic; `bool` counts as 0 or 1.
+fn is_numeric(ty: &Type) -> bool {
+    ty.is_integer() || *ty == Type::Bool
+}
+
+/// Type of `lhs op rhs` for two numeric operands: the operand type if both
+/// agree, otherwise a full word, unsigned if either side is.
+fn arithmetic_result(lhs: &Type, rhs: &Type) -> Type {
+    if lhs == rhs && lhs.is_integer() {
+        lhs.clone()
+    } else if *lhs == Type::U32 || *rhs == Type::U32 {
+        Type::U32
+    } else {
+        Type::I32
+    }
+}
+
+/// Value of an integer literal, including a negated one, or of a constant.
+fn literal_value(expr: &AstNode) -> Option<i64> {
+    match expr {
+        AstNode::Number(n, _) => Some(*n),
+        AstNode::Constant(_, value, _, _) => Some(*value),
+        AstNode::Unary(UnaryOp::Neg, operand, _) => literal_value(operand).map(|n| -n),
+        _ => None,
+    }
+}
+
+/// Value of an expression known at compile time, e.g. `SIZE - 1`.
+fn constant_value(expr: &AstNode) -> Option<i64> {
+    evaluate(expr).ok()
+}
+
+/// Whether `value`, the folded value of `expr`, is negative without `expr`
+/// being a plain literal, which `expect` already rejects for a `u32`.
+fn is_folded_negative(expr: &AstNode, value: i64) -> bool {
+    value < 0 && literal_value(expr).is_none()
+}
+
+struct Checker<'a> {
+    resolution: &'a Resolution,
+    table: TypeTable,
+    returns: Type, // result type of the function being checked
+    errors: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn check_statement(&mut self, stmt: &AstNode) {
+        match stmt {
+            AstNode::Program(statements, _) => {
+                // Signatures first, calls may come before the definition
+                for stmt in statements {
+                    self.collect_signature(stmt);
+                }
+                for stmt in statements {
+                    self.check_statement(stmt);
+                }
+            }
+
+            AstNode::FunctionDefinition(_, params, returns, body, _) => {
+                self.returns = returns.clone().unwrap_or(Type::I32);
+                for param in params {
+                    if let AstNode::Parameter(_, ty, span) = param {
+                        self.declare(*span, ty.clone());
+                    }
+                }
+                for stmt in body {
+                    self.check_statement(stmt);
+                }
+            }
+
+            AstNode::VariableDeclaration(_, _, annotation, value, span) => {
+                let ty = match (annotation, &**value) {
+                    (Some(Type::Array(_, _)), _) => {
+                        self.errors.push(
+                            Diagnostic::error(
+                                E_MISMATCHED_TYPES,
+                                "arrays can't be initialized with `let`",
+                                *span,
+                            )
+                            .with_label("array type used here")
+                            .with_note("declare a buffer with `buf[N] name;` instead"),
+                        );
+                        return;
+                    }
+                    (Some(ty @ Type::Struct(_)), _) => {
+                        self.errors.push(
+                            Diagnostic::error(
+                                E_MISMATCHED_TYPES,
+                                format!("`{}` can't be initialized with `let`", ty),
+                                *span,
+                            )
+                            .with_label("struct type used here")
+                            .with_note(format!("declare it with `buf name: {};` instead", ty)),
+                        );
+                        return;
+                    }
+                    (Some(Type::Str), AstNode::String(_, _)) => Type::Str,
+                    (Some(Type::Str), value) => {
+                        self.type_of(value);
+                        self.errors.push(not_a_string_literal(value));
+                        return;
+                    }
+                    (Some(ty), value) => {
+                        self.expect(value, ty);
+                        ty.clone()
+                    }
+                    // String literals stay strings, so their length is kept
+                    (None, AstNode::String(_, _)) => Type::Str,
+                    (None, value) => match self.type_of(value) {
+                        Some(Type::Str) => {
+                            let message = "strings can't be copied into another variable";
+                            self.errors.push(read_only_string(value.span(), message));
+                            return;
+                        }
+                        Some(ty) => ty.decay(),
+                        None => return,
+                    },
+                };
+                self.declare(*span, ty);
+            }
+
+            AstNode::Identifier(name, count, element, span) => {
+                if matches!(element, Type::Array(_, _) | Type::Str) {
+                    self.errors.push(
+                        Diagnostic::error(
+                            E_MISMATCHED_TYPES,
+                            format!("buffer `{}` can't hold elements of type `{}`", name, element),
+                            *span,
+                        )
+                        .with_label("invalid element type")
+                        .with_note("buffers hold integers, booleans, pointers or structs"),
+                    );
+                    return;
+                }
+                // The frame itself is only 8-byte aligned
+                if element.align() > MAX_ALIGN {
+                    self.errors.push(
+                        Diagnostic::error(
+                            E_MISMATCHED_TYPES,
+                            format!("buffer `{}` needs {}-byte alignment", name, element.align()),
+                            *span,
+                        )
+                        .with_label("stack buffers are aligned to at most 8 bytes"),
+                    );
+                    return;
+                }
+                match (count, element) {
+                    (Some(count), _) => {
+                        self.declare(*span, Type::Array(Box::new(element.clone()), *count))
+                    }
+                    (None, Type::Struct(_)) => self.declare(*span, element.clone()),
+                    (None, _) => self.errors.push(
+                        Diagnostic::error(
+                            E_MISMATCHED_TYPES,
+                            format!("buffer `{}` needs a size", name),
+                            *span,
+                        )
+                        .with_label("no element count")
+                        .with_note(format!(
+                            "only structs can be declared without one, use `buf[N] {}: {};`",
+                            name, element
+                        )),
+                    ),
+                }
+            }
+
+            AstNode::FieldAssignment(base, field, value, span) => {
+                match self.field_type(base, field, *span) {
+                    Some(ty) => self.expect(value, &ty),
+                    None => {
+                        self.type_of(value);
+                    }
+                }
+            }
+
+            AstNode::Assignment(_, value, span) => {
+                match self.symbol_type(*span) {
+                    Some(ty) => self.expect(value, &ty),
+                    None => {
+                        self.type_of(value);
+                    }
+                }
+            }
+
+            AstNode::DerefAssignment(pointer, value, span) => {
+                if self.type_of(pointer) == Some(Type::Str) {
+                    self.type_of(value);
+                    let message = match &**pointer {
+                        AstNode::Variable(name, _) => {
+                            format!("cannot write into string `{}`", name)
+                        }
+                        _ => "cannot write into a string".to_string(),
+                    };
+                    let diagnostic = Diagnostic::error(E_MISMATCHED_TYPES, message, *span)
+                    .with_label("string literals are read-only")
+                    .with_note("copy it into a buffer declared with `buf[N] name;` first");
+                    self.errors.push(self.with_declaration(diagnostic, pointer.span()));
+                    return;
+                }
+
+                match self.pointee_type(pointer, *span) {
+                    Some(ty) => self.expect(value, &ty),
+                    None => {
+                        self.type_of(value);
+                    }
+                }
+            }
+
+            AstNode::IndexAssignment(name, index, value, span) => {
+                match self.element_type(name, index, *span, true) {
+                    Some(element) => self.expect(value, &element),
+                    None => {
+                        self.type_of(value);
+                    }
+                }
+            }
+
+            AstNode::If(condition, then_body, else_body, _) => {
+                self.check_condition(condition);
+                for stmt in then_body.iter().chain(else_body) {
+                    self.check_statement(stmt);
+                }
+            }
+
+            AstNode::While(condition, body, _) => {
+                self.check_condition(condition);
+                for stmt in body {
+                    self.check_statement(stmt);
+                }
+            }
+
+            AstNode::Loop(body, _) => {
+                for stmt in body {
+                    self.check_statement(stmt);
+                }
+            }
+
+            AstNode::Return(Some(value), _) => {
+                let returns = self.returns.clone();
+                self.expect(value, &returns);
+            }
+
+            AstNode::Return(None, _) | AstNode::Break(_) | AstNode::Continue(_) => {}
+
+            // Calls and syscalls used as statements
+            expr => {
+                self.type_of(expr);
+            }
+        }
+    }
+
+    fn collect_signature(&mut self, stmt: &AstNode) {
+        let AstNode::FunctionDefinition(_, params, returns, _, span) = stmt else {
+            return;
+        };
+        let Some(id) = self.resolution.id_at(*span) else {
+            return;
+        };
+
+        let mut types = Vec::new();
+        for param in params {
+            if let AstNode::Parameter(name, ty, param_span) = param {
+                if matches!(ty, Type::Array(_, _) | Type::Str | Type::Struct(_)) {
+                    self.errors.push(
+                        Diagnostic::error(
+                            E_MISMATCHED_TYPES,
+                            format!("parameter `{}` can't be of type `{}`", name, ty),
+                            *param_span,
+                        )
+                        .with_label("arguments are passed as single words")
+                        .with_note("pass a pointer such as `*u8` instead"),
+                    );
+                }
+                types.push(ty.clone());
+            }
+        }
+
+        let returns = returns.clone().unwrap_or(Type::I32);
+        if matches!(returns, Type::Array(_, _) | Type::Str | Type::Struct(_)) {
+            self.errors.push(
+                Diagnostic::error(
+                    E_MISMATCHED_TYPES,
+                    format!("functions can't return `{}`", returns),
+                    *span,
+                )
+                .with_label("results are returned in a single register")
+                .with_note("return a pointer such as `*u8` instead"),
+            );
+        }
+
+        self.table.signatures.insert(
+            id,
+            Signature {
+                params: types,
+                returns,
+            },
+        );
+    }
+
+    fn declare(&mut self, span: Span, ty: Type) {
+        if let Some(id) = self.resolution.id_at(span) {
+            self.table.symbols.insert(id, ty);
+        }
+    }
+
+    /// Type of the symbol declared or used at `span`.
+    fn symbol_type(&self, span: Span) -> Option<Type> {
+        let id = self.resolution.id_at(span)?;
+        self.table.symbols.get(&id).cloned()
+    }
+
+    fn mismatch(&mut self, span: Span, expected: &Type, found: &Type) {
+        self.errors.push(
+            Diagnostic::error(
+                E_MISMATCHED_TYPES,
+                format!("mismatched types: expected `{}`, found `{}`", expected, found),
+                span,
+            )
+            .with_label(format!("expected `{}`", expected)),
+        );
+    }
+
+    /// Checks that `expr` can be stored where a value of type `expected` goes.
+    fn expect(&mut self, expr: &AstNode, expected: &Type) {
+        // Only single values are copied, e.g. a field of a nested struct isn't
+        if expected.is_aggregate() {
+            self.type_of(expr);
+            self.errors.push(
+                Diagnostic::error(
+                    E_MISMATCHED_TYPES,
+                    format!("cannot assign a whole `{}`", expected),
+                    expr.span(),
+                )
+                .with_label("assigned here")
+                .with_note("assign its fields or elements one by one"),
+            );
+            return;
+        }
+
+        // Integer literals take on the expected type if they fit, and 0 is NULL
+        if let Some(value) = literal_value(expr) {
+            if expected.is_integer() {
+                if !expected.fits(value) {
+                    let message = match expr {
+                        AstNode::Constant(name, _, _, _) => {
+                            format!("constant `{}` ({}) doesn't fit in `{}`", name, value, expected)
+                        }
+                        _ => format!("literal `{}` doesn't fit in `{}`", value, expected),
+                    };
+                    self.errors.push(
+                        Diagnostic::error(E_INVALID_LITERAL, message, expr.span())
+                            .with_label(format!("out of range for `{}`", expected)),
+                    );
+                }
+                self.type_of(expr);
+                return;
+            }
+            if value == 0 && matches!(expected, Type::Ptr(_)) {
+                self.type_of(expr);
+                return;
+            }
+        }
+
+        let Some(found) = self.type_of(expr) else {
+            return;
+        };
+        if !assignable(&found, expected) {
+            self.mismatch(expr.span(), expected, &found);
+        }
+    }
+
+    /// Conditions may be booleans, integers (non-zero is true) or pointers.
+    fn check_condition(&mut self, condition: &AstNode) {
+        let Some(ty) = self.type_of(condition) else {
+            return;
+        };
+        if !is_numeric(&ty) && !matches!(ty, Type::Ptr(_)) {
+            self.mismatch(condition.span(), &Type::Bool, &ty);
+        }
+    }
+
+    /// Type of `expr`, or `None` if it has none or an error was reported.
+    fn type_of(&mut self, expr: &AstNode) -> Option<Type> {
+        let ty = self.infer(expr)?;
+        let span = expr.span();
+        self.table.expressions.insert((span.start, span.end), ty.clone());
+        Some(ty)
+    }
+
+    fn infer(&mut self, expr: &AstNode) -> Option<Type> {
+        match expr {
+            AstNode::Number(n, _) if !Type::I32.fits(*n) => Some(Type::U32),
+            AstNode::Number(_, _) => Some(Type::I32),
+            AstNode::Bool(_, _) => Some(Type::Bool),
+            AstNode::Constant(_, _, ty, _) => Some(ty.clone()),
+            AstNode::String(_, _) => Some(Type::Str),
+            AstNode::Variable(_, span) => self.symbol_type(*span),
+
+            AstNode::Index(name, index, span) => self.element_type(name, index, *span, false),
+
+            AstNode::Field(base, field, span) => self.field_type(base, field, *span),
+
+            AstNode::Slice(_, _, _, span) => {
+                self.errors.push(
+                    Diagnostic::error(E_MISMATCHED_TYPES, "slices can't be used as values", *span)
+                        .with_label("slice used here")
+                        .with_note("slices can be passed to syscalls that take a string or buffer"),
+                );
+                None
+            }
+
+            AstNode::Unary(UnaryOp::Neg, operand, span) => {
+                let ty = self.type_of(operand)?;
+                if !is_numeric(&ty) {
+                    self.errors.push(
+                        Diagnostic::error(
+                            E_MISMATCHED_TYPES,
+                            format!("cannot negate a value of type `{}`", ty),
+                            *span,
+                        )
+                        .with_label("not a number"),
+                    );
+                    return None;
+                }
+                Some(arithmetic_result(&ty, &ty))
+            }
+
+            AstNode::Unary(UnaryOp::Not, operand, _) => {
+                self.check_condition(operand);
+                Some(Type::Bool)
+            }
+
+            AstNode::Unary(UnaryOp::AddressOf, operand, span) => match &**operand {
+                AstNode::Variable(_, _) | AstNode::Index(_, _, _)
+                    if self.symbol_type(operand.span()) == Some(Type::Str) =>
+                {
+                    self.type_of(operand);
+                    let message = "cannot take the address of a string";
+                    self.errors.push(read_only_string(*span, message));
+                    None
+                }
+                AstNode::Variable(_, _) => match self.type_of(operand)? {
+                    // Same as the decayed value, a pointer to the first element
+                    ty @ (Type::Array(_, _) | Type::Str) => Some(ty.decay()),
+                    ty => Some(Type::Ptr(Box::new(ty))),
+                },
+                AstNode::Index(_, _, _)
+                | AstNode::Field(_, _, _)
+                | AstNode::Unary(UnaryOp::Deref, _, _) => {
+                    Some(Type::Ptr(Box::new(self.type_of(operand)?)))
+                }
+                _ => {
+                    self.type_of(operand);
+                    self.errors.push(
+                        Diagnostic::error(
+                            E_MISMATCHED_TYPES,
+                            "cannot take the address of a temporary value",
+                            *span,
+                        )
+                        .with_label("not stored anywhere")
+                        .with_note("store it in a variable first and take the variable's address"),
+                    );
+                    None
+                }
+            },
+
+            AstNode::Unary(UnaryOp::Deref, operand, span) => self.pointee_type(operand, *span),
+
+            AstNode::Binary(BinaryOp::And | BinaryOp::Or, lhs, rhs, _) => {
+                self.check_condition(lhs);
+                self.check_condition(rhs);
+                Some(Type::Bool)
+            }
+
+            AstNode::Binary(op, lhs, rhs, span) => {
+                let lhs_type = self.type_of(lhs);
+                let rhs_type = self.type_of(rhs);
+                if !op.is_comparison() && [&lhs_type, &rhs_type].contains(&&Some(Type::Str)) {
+                    let message = format!("cannot apply `{}` to a string", op.as_str());
+                    self.errors.push(read_only_string(*span, message));
+                    return None;
+                }
+                let (lhs_type, rhs_type) = (lhs_type?.decay(), rhs_type?.decay());
+                let null = |expr: &AstNode| literal_value(expr) == Some(0);
+
+                let result = match (&lhs_type, &rhs_type) {
+                    (l, r) if is_numeric(l) && is_numeric(r) => {
+                        if op.is_comparison() {
+                            Some(Type::Bool)
+                        } else {
+                            Some(arithmetic_result(l, r))
+                        }
+                    }
+                    (Type::Ptr(_), Type::Ptr(_)) if op.is_comparison() => Some(Type::Bool),
+                    (Type::Ptr(_), _) if op.is_comparison() && null(rhs) => Some(Type::Bool),
+                    (_, Type::Ptr(_)) if op.is_comparison() && null(lhs) => Some(Type::Bool),
+                    (Type::Ptr(_), r) if is_numeric(r) => match op {
+                        BinaryOp::Add | BinaryOp::Sub => Some(lhs_type.clone()),
+                        _ => None,
+                    },
+                    (l, Type::Ptr(_)) if is_numeric(l) && *op == BinaryOp::Add => {
+                        Some(rhs_type.clone())
+                    }
+                    (Type::Ptr(_), Type::Ptr(_)) if *op == BinaryOp::Sub => Some(Type::I32),
+                    _ => None,
+                };
+
+                if result.is_none() {
+                    self.errors.push(
+                        Diagnostic::error(
+                            E_MISMATCHED_TYPES,
+                            format!(
+                                "cannot apply `{}` to `{}` and `{}`",
+                                op.as_str(),
+                                lhs_type,
+                                rhs_type
+                            ),
+                            *span,
+                        )
+                        .with_label(format!("`{}` and `{}`", lhs_type, rhs_type)),
+                    );
+                }
+                result
+            }
+
+            AstNode::Call(_, args, span) => {
+                let signature = self
+                    .resolution
+                    .id_at(*span)
+                    .and_then(|id| self.table.signature(id))
+                    .cloned();
+                let Some(signature) = signature else {
+                    for arg in args {
+                        self.type_of(arg);
+                    }
+                    return None;
+                };
+
+                // A wrong argument count is reported by the generator
+                for (index, arg) in args.iter().enumerate() {
+                    match signature.params.get(index) {
+                        Some(param) => self.expect(arg, param),
+                        None => {
+                            self.type_of(arg);
+                        }
+                    }
+                }
+                Some(signature.returns)
+            }
+
+            AstNode::Syscall(name, args, _) => {
+                let spec = find_syscall(name)?;
+                for ((arg_name, kind), arg) in spec.args.iter().zip(args) {
+                    self.check_syscall_argument(arg, arg_name, *kind, spec);
+                }
+                self.check_syscall_lengths(args, spec);
+
+                match spec.returns {
+                    ReturnKind::Never => None,
+                    ReturnKind::Ptr => Some(Type::Ptr(Box::new(Type::U8))),
+                    ReturnKind::Fd | ReturnKind::Size | ReturnKind::Int => Some(Type::I32),
+                }
+            }
+
+            // The raw form passes whatever it gets straight to the kernel
+            AstNode::RawSyscall(number, args, _) => {
+                self.type_of(number);
+                for arg in args {
+                    self.type_of(arg);
+                }
+                Some(Type::I32)
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Numeric kinds take integers (and `int` also pointers, e.g. for
+    /// `ioctl`). Pointer kinds take strings, buffers, slices, pointers and
+    /// 0 for NULL; the kernel can't write into a string.
+    fn check_syscall_argument(
+        &mut self,
+        arg: &AstNode,
+        arg_name: &str,
+        kind: ArgKind,
+        spec: &SyscallSpec,
+    ) {
+        let writable = kind == ArgKind::Out;
+
+        let (accepted, expected) = match kind {
+            ArgKind::Fd | ArgKind::Len => {
+                if literal_value(arg).is_some() {
+                    self.type_of(arg);
+                    return;
+                }
+                let Some(ty) = self.type_of(arg) else {
+                    return;
+                };
+                (is_numeric(&ty), ty)
+            }
+
+            ArgKind::Int => {
+                let Some(ty) = self.type_of(arg) else {
+                    return;
+                };
+                (is_numeric(&ty) || matches!(ty.decay(), Type::Ptr(_)), ty)
+            }
+
+            ArgKind::Ptr | ArgKind::Out | ArgKind::Path => {
+                if literal_value(arg) == Some(0) {
+                    self.type_of(arg);
+                    return;
+                }
+
+                let ty = match arg {
+                    AstNode::Slice(name, from, to, span) => {
+                        for bound in [from, to].into_iter().flatten() {
+                            self.expect(bound, &Type::U32);
+                        }
+                        let ty = self.symbol_type(*span);
+                        match &ty {
+                            Some(Type::Array(element, _)) if **element != Type::U8 => {
+                                self.errors.push(
+                                    Diagnostic::error(
+                                        E_MISMATCHED_TYPES,
+                                        format!("cannot slice `{}`, it isn't a byte buffer", name),
+                                        arg.span(),
+                                    )
+                                    .with_label(format!("`{}` holds `{}` elements", name, element))
+                                    .with_note("pass the whole buffer and a length in bytes"),
+                                );
+                                return;
+                            }
+                            Some(array @ Type::Array(_, _)) => {
+                                let (from, to) = (from.as_deref(), to.as_deref());
+                                self.check_slice(name, from, to, *span, array);
+                            }
+                            _ => {}
+                        }
+                        ty
+                    }
+                    _ => self.type_of(arg),
+                };
+                let Some(ty) = ty else {
+                    return;
+                };
+
+                let accepted = match ty {
+                    Type::Array(_, _) | Type::Struct(_) => true,
+                    Type::Str => !writable,
+                    Type::Ptr(_) => !matches!(arg, AstNode::Slice(_, _, _, _)),
+                    _ => false,
+                };
+                (accepted, ty)
+            }
+        };
+
+        if !accepted {
+            let wanted = match kind {
+                ArgKind::Fd | ArgKind::Len => "an integer",
+                ArgKind::Int => "an integer or pointer",
+                ArgKind::Out => "a buffer",
+                ArgKind::Ptr | ArgKind::Path => "a string or buffer",
+            };
+            self.errors.push(
+                Diagnostic::error(
+                    E_MISMATCHED_TYPES,
+                    format!("expected {} for `{}`, found `{}`", wanted, arg_name, expected),
+                    arg.span(),
+                )
+                .with_label(format!("expected {}", wanted))
+                .with_note(format!("the signature is `{}`", spec.signature())),
+            );
+        }
+    }
+
+    /// Type of what `pointer` points to, for `*pointer`. Strings and buffers
+    /// point to their first element.
+    fn pointee_type(&mut self, pointer: &AstNode, span: Span) -> Option<Type> {
+        match self.type_of(pointer)?.decay() {
+            Type::Ptr(target) => Some(*target),
+            ty => {
+                self.errors.push(
+                    Diagnostic::error(
+                        E_MISMATCHED_TYPES,
+                        format!("cannot dereference a value of type `{}`", ty),
+                        span,
+                    )
+                    .with_label("not a pointer"),
+                );
+                None
+            }
+        }
+    }
+
+    /// Type of `base.field`, where `base` is a struct or a pointer to one.
+    fn field_type(&mut self, base: &AstNode, field: &str, span: Span) -> Option<Type> {
+        let def = match self.type_of(base)?.decay() {
+            Type::Ptr(target) => match *target {
+                Type::Struct(def) => Some(def),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some(def) = def else {
+            let ty = self.table.expression(base).cloned()?;
+            self.errors.push(
+                Diagnostic::error(
+                    E_MISMATCHED_TYPES,
+                    format!("no field `{}` on a value of type `{}`", field, ty),
+                    span,
+                )
+                .with_label("not a struct or a pointer to one"),
+            );
+            return None;
+        };
+
+        match def.field(field) {
+            Some(field) => Some(field.ty.clone()),
+            None => {
+                self.errors.push(unknown_field(&def, field, span));
+                None
+            }
+        }
+    }
+
+    /// Type of the element `name[index]` refers to. Constant indices past
+    /// the end of a buffer are rejected; string literals can only be read.
+    fn element_type(
+        &mut self,
+        name: &str,
+        index: &AstNode,
+        span: Span,
+        writing: bool,
+    ) -> Option<Type> {
+        self.expect(index, &Type::U32);
+        let ty = self.symbol_type(span)?;
+
+        match ty {
+            Type::Array(element, count) => {
+                if let Some(value) = constant_value(index)
+                    && (value >= count as i64 || is_folded_negative(index, value))
+                {
+                    let diagnostic = Diagnostic::error(
+                        E_OUT_OF_BOUNDS,
+                        format!("index `{}` is out of range for `{}`", value, name),
+                        index.span(),
+                    )
+                    .with_label(format!("`{}` has {} elements", name, count));
+                    self.errors.push(self.with_declaration(diagnostic, span));
+                }
+                Some(*element)
+            }
+            Type::Str if !writing => Some(Type::U8),
+            Type::Str => {
+                let diagnostic = Diagnostic::error(
+                    E_MISMATCHED_TYPES,
+                    format!("cannot write into string `{}`", name),
+                    span,
+                )
+                .with_label("string literals are read-only")
+                .with_note("copy it into a buffer declared with `buf[N] name;` first");
+                self.errors.push(self.with_declaration(diagnostic, span));
+                None
+            }
+            ty => {
+                self.errors.push(
+                    Diagnostic::error(
+                        E_MISMATCHED_TYPES,
+                        format!("cannot index into a value of type `{}`", ty),
+                        span,
+                    )
+                    .with_label(format!("`{}` is not a string or buffer", name)),
+                );
+                None
+            }
+        }
+    }
+
+    /// Rejects constant slice bounds that fall outside the buffer or cross
+    /// each other. Bounds computed at runtime are left to `--bounds-checks`.
+    fn check_slice(
+        &mut self,
+        name: &str,
+        from: Option<&AstNode>,
+        to: Option<&AstNode>,
+        span: Span,
+        array: &Type,
+    ) {
+        let size = array.size() as i64;
+        let start = from.and_then(constant_value);
+        let end = to.and_then(constant_value);
+
+        for (bound, value) in [(from, start), (to, end)] {
+            if let (Some(bound), Some(value)) = (bound, value)
+                && (value > size || is_folded_negative(bound, value))
+            {
+                let diagnostic = Diagnostic::error(
+                    E_OUT_OF_BOUNDS,
+                    format!("slice bound `{}` is out of range for `{}`", value, name),
+                    bound.span(),
+                )
+                .with_label(format!("`{}` is {} bytes long", name, size));
+                self.errors.push(self.with_declaration(diagnostic, span));
+                return;
+            }
+        }
+
+        if let (Some(start), Some(end)) = (start, end)
+            && start > end
+        {
+            self.errors.push(
+                Diagnostic::error(
+                    E_OUT_OF_BOUNDS,
+                    format!("slice of `{}` starts at {} but ends at {}", name, start, end),
+                    span,
+                )
+                .with_label("the start is past the end"),
+            );
+        }
+    }
+
+    /// Rejects a constant `count` larger than the string, buffer or slice
+    /// right before it, e.g. `$read(0, input, 100)` into a 64-byte buffer.
+    fn check_syscall_lengths(&mut self, args: &[AstNode], spec: &SyscallSpec) {
+        for (index, (arg_name, kind)) in spec.args.iter().enumerate().skip(1) {
+            if *kind != ArgKind::Len {
+                continue;
+            }
+            let (Some(count), Some(pointer)) = (args.get(index), args.get(index - 1)) else {
+                continue;
+            };
+            let (Some(value), Some(length)) = (constant_value(count), self.known_length(pointer))
+            else {
+                continue;
+            };
+
+            if !(0..=length).contains(&value) {
+                let diagnostic = Diagnostic::error(
+                    E_OUT_OF_BOUNDS,
+                    format!(
+                        "`${}` would access {} bytes, but only {} are available",
+                        spec.name, value, length
+                    ),
+                    count.span(),
+                )
+                .with_label(format!("`{}` is out of range", arg_name))
+                .with_secondary(pointer.span(), format!("{} bytes long", length));
+                self.errors.push(diagnostic);
+            }
+        }
+    }
+
+    /// Length in bytes of a string literal, a buffer or struct, or a slice of
+    /// a buffer with constant bounds.
+    fn known_length(&self, expr: &AstNode) -> Option<i64> {
+        match expr {
+            AstNode::String(bytes, _) => Some(bytes.len() as i64),
+            AstNode::Variable(_, span) => match self.symbol_type(*span)? {
+                ty @ (Type::Array(_, _) | Type::Struct(_)) => Some(ty.size() as i64),
+                _ => None,
+            },
+            AstNode::Slice(_, from, to, span) => {
+                let size = match self.symbol_type(*span)? {
+                    array @ Type::Array(_, _) => array.size() as i64,
+                    _ => return None,
+                };
+                let start = match from {
+                    Some(from) => constant_value(from)?,
+                    None => 0,
+                };
+                let end = match to {
+                    Some(to) => constant_value(to)?,
+                    None => size,
+                };
+                Some(end - start)
+            }
+            _ => None,
+        }
+    }
+
+    /// Points at the declaration of the symbol used at `span`.
+    fn with_declaration(&self, diagnostic: Diagnostic, span: Span) -> Diagnostic {
+        match self.resolution.symbol_at(span) {
+            Some(symbol) => diagnostic.with_secondary(symbol.span, "declared here"),
+            None => diagnostic,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        extra::diagnostic::E_UNKNOWN_FIELD,
+        frontend::{parser::parse, resolver::resolve, tokenizer::tokenize},
+    };
+
+    /// Codes of the diagnostics reported for `source`, in order.
+    fn codes(source: &str) -> Vec<&'static str> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let resolution = resolve(&program).unwrap();
+        match check(&program, &resolution) {
+            Ok(_) => Vec::new(),
+            Err(diagnostics) => diagnostics.iter().map(|d| d.code).collect(),
+        }
+    }
+
+    #[test]
+    fn literals_must_fit_their_type() {
+        let source = "fn main() { let x: u8 = 300; let y: i32 = 3000000000; \
+                      let z: u32 = 4294967295; let w: i8 = -128; }";
+        assert_eq!(codes(source), [E_INVALID_LITERAL, E_INVALID_LITERAL]);
+    }
+
+    #[test]
+    fn mismatched_types() {
+        let source = "fn f(a: u8) -> bool { return a; } \
+                      fn main() { let s: str = 1; $write(1, 5, 1); let b: bool = 1; }";
+        assert_eq!(codes(source), [E_MISMATCHED_TYPES; 4]);
+    }
+
+    #[test]
+    fn constant_slice_bounds() {
+        let source = "fn main() { buf[8] b; $write(1, b[0..8]); $write(1, b[2..9]); \
+                      $write(1, b[5..3]); }";
+        assert_eq!(codes(source), [E_OUT_OF_BOUNDS, E_OUT_OF_BOUNDS]);
+    }
+
+    #[test]
+    fn constant_syscall_lengths() {
+        let source = "fn main() { buf[8] b; $write(1, b, 8); $write(1, b, 9); \
+                      $read(0, b[..4], 5); $write(1, \"abc\", 4); }";
+        assert_eq!(codes(source), [E_OUT_OF_BOUNDS; 3]);
+    }
+
+    #[test]
+    fn constant_indices() {
+        let source = "fn main() { buf[8] b; buf[2] w: u32; let s = \"abc\"; \
+                      b[7] = s[1]; b[8] = 1; w[1] = 70000; w[2] = 1; }";
+        assert_eq!(codes(source), [E_OUT_OF_BOUNDS; 2]);
+    }
+
+    #[test]
+    fn strings_can_only_be_read() {
+        let source = "fn main() { let s = \"abc\"; let x: u8 = 1; s[0] = 1; x[0] = 1; }";
+        assert_eq!(codes(source), [E_MISMATCHED_TYPES; 2]);
+        assert_eq!(codes("fn main() { buf[2] b: [u8; 4]; }"), [E_MISMATCHED_TYPES]);
+    }
+
+    #[test]
+    fn pointers() {
+        let source = "fn main() { buf[4] w: u32; let p = &w[1]; let q: *u8 = p; let r: *u32 = q; \
+                      *p = 7; let n: i32 = p - &w[0]; }";
+        assert!(codes(source).is_empty());
+
+        let source = "fn main() { buf[4] w: u32; let p: *u16 = &w[0]; let x = 1; *x = 2; \
+                      let a = &(x + 1); }";
+        assert_eq!(codes(source), [E_MISMATCHED_TYPES; 3]);
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let source = "struct p { x: i32 } fn main() { buf q: p; q.y = 1; $exit(q.x.z); }";
+        assert_eq!(codes(source), [E_UNKNOWN_FIELD, E_MISMATCHED_TYPES]);
+    }
+    #[test]
+    fn negative_folded_index() {
+        let source = "const ONE: u32 = 1; fn main() { buf[8] b; b[ONE - 2] = 1; b[2 - 3] = 1; }";
+        assert_eq!(codes(source), [E_OUT_OF_BOUNDS; 2]);
+    }
+
+    #[test]
+    fn negative_folded_slice_bounds() {
+        let source = "const ONE: u32 = 1; fn main() { buf[8] b; $write(1, b[ONE - 3..4]); \
+                      $write(1, b[..1 - 2]); }";
+        assert_eq!(codes(source), [E_OUT_OF_BOUNDS; 2]);
+    }
+    #[test]
+    fn strings_never_become_writable_pointers() {
+        let source = "fn main() { let s = \"abc\"; *s = 65; let p: *u8 = s; let q = s; \
+                      let r = &s[1]; let t = s + 1; let u: *u8 = \"def\"; }";
+        assert_eq!(codes(source), [E_MISMATCHED_TYPES; 6]);
+        assert!(codes("fn main() { let s = \"abc\"; let c: u8 = *s + s[1]; }").is_empty());
+    }
+}
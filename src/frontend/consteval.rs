@@ -0,0 +1,172 @@
+use crate::{
+    extra::diagnostic::{CompileResult, Diagnostic, E_NOT_CONSTANT},
+    frontend::{
+        parser::{AstNode, BinaryOp, UnaryOp},
+        types::Type,
+    },
+};
+
+/// Folds an expression made of number and boolean literals, constants and
+/// operators into its value. Every intermediate value has to fit in 32 bits,
+/// signed or unsigned. Comparisons, divisions and right shifts read their
+/// operands as unsigned if either side is a `u32`, as the generated code
+/// does, so the result is the same as computing it at runtime.
+pub fn evaluate(expr: &AstNode) -> CompileResult<i64> {
+    fold(expr).map(|(value, _)| value)
+}
+
+/// Value of `expr` and whether it is a `u32`, following the typing rules of
+/// the checker: literals past `i32::MAX` are `u32`, and so is any arithmetic
+/// with a `u32` operand.
+fn fold(expr: &AstNode) -> CompileResult<(i64, bool)> {
+    let (value, unsigned) = match expr {
+        AstNode::Number(n, _) => (*n, *n > i32::MAX as i64),
+        AstNode::Bool(value, _) => (i64::from(*value), false),
+        AstNode::Constant(_, value, ty, _) => (*value, *ty == Type::U32),
+
+        AstNode::Unary(UnaryOp::Neg, operand, _) => {
+            let (value, unsigned) = fold(operand)?;
+            (-value, unsigned)
+        }
+        AstNode::Unary(UnaryOp::Not, operand, _) => (i64::from(evaluate(operand)? == 0), false),
+
+        // Both sides are evaluated, there are no side effects to skip
+        AstNode::Binary(BinaryOp::And, lhs, rhs, _) => {
+            (i64::from(evaluate(lhs)? != 0 && evaluate(rhs)? != 0), false)
+        }
+        AstNode::Binary(BinaryOp::Or, lhs, rhs, _) => {
+            (i64::from(evaluate(lhs)? != 0 || evaluate(rhs)? != 0), false)
+        }
+
+        AstNode::Binary(op, lhs, rhs, span) => {
+            let ((lhs, lhs_unsigned), (rhs, rhs_unsigned)) = (fold(lhs)?, fold(rhs)?);
+            let unsigned = lhs_unsigned || rhs_unsigned;
+            // What a register holding the value means to the instruction
+            let word = |value: i64| {
+                if unsigned { value as u32 as i64 } else { value as i32 as i64 }
+            };
+            let (a, b) = (word(lhs), word(rhs));
+
+            let value = match op {
+                BinaryOp::Add => lhs + rhs,
+                BinaryOp::Sub => lhs - rhs,
+                BinaryOp::Mul => lhs.saturating_mul(rhs),
+                BinaryOp::Div | BinaryOp::Mod if rhs == 0 => {
+                    return Err(Diagnostic::error(
+                        E_NOT_CONSTANT,
+                        "division by zero in a constant expression",
+                        *span,
+                    )
+                    .with_label("the divisor is 0")
+                    .into());
+                }
+                BinaryOp::Div => a / b,
+                BinaryOp::Mod => a % b,
+                BinaryOp::BitAnd => lhs & rhs,
+                BinaryOp::BitOr => lhs | rhs,
+                BinaryOp::BitXor => lhs ^ rhs,
+                BinaryOp::Shl | BinaryOp::Shr if !(0..32).contains(&rhs) => {
+                    return Err(Diagnostic::error(
+                        E_NOT_CONSTANT,
+                        format!("cannot shift by {} bits", rhs),
+                        *span,
+                    )
+                    .with_label("shift amounts go from 0 to 31")
+                    .into());
+                }
+                BinaryOp::Shl => lhs << rhs,
+                BinaryOp::Shr => a >> rhs,
+                BinaryOp::Eq => i64::from(a == b),
+                BinaryOp::Ne => i64::from(a != b),
+                BinaryOp::Lt => i64::from(a < b),
+                BinaryOp::Le => i64::from(a <= b),
+                BinaryOp::Gt => i64::from(a > b),
+                BinaryOp::Ge => i64::from(a >= b),
+                BinaryOp::And | BinaryOp::Or => unreachable!(),
+            };
+            (value, unsigned && !op.is_comparison())
+        }
+
+        _ => {
+            return Err(Diagnostic::error(
+                E_NOT_CONSTANT,
+                "expected a constant expression",
+                expr.span(),
+            )
+            .with_label("not known at compile time")
+            .with_note("only literals, constants, `sizeof` and `offsetof` can be combined here")
+            .into());
+        }
+    };
+
+    if !(i32::MIN as i64..=u32::MAX as i64).contains(&value) {
+        return Err(Diagnostic::error(
+            E_NOT_CONSTANT,
+            format!("constant expression overflows: {}", value),
+            expr.span(),
+        )
+        .with_label("doesn't fit in 32 bits")
+        .into());
+    }
+
+    Ok((value, unsigned))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::span::Span;
+
+    fn number(n: i64) -> AstNode {
+        AstNode::Number(n, Span::default())
+    }
+
+    fn binary(op: BinaryOp, lhs: AstNode, rhs: AstNode) -> AstNode {
+        AstNode::Binary(op, Box::new(lhs), Box::new(rhs), Span::default())
+    }
+
+    fn error_code(expr: &AstNode) -> Option<&'static str> {
+        evaluate(expr).err().map(|diagnostic| diagnostic.code)
+    }
+
+    #[test]
+    fn folds_operators() {
+        let sum = binary(BinaryOp::Add, number(2), number(3));
+        assert_eq!(evaluate(&binary(BinaryOp::Mul, sum, number(4))).unwrap(), 20);
+        assert_eq!(evaluate(&binary(BinaryOp::Shl, number(1), number(31))).unwrap(), 1 << 31);
+        assert_eq!(evaluate(&binary(BinaryOp::Div, number(-7), number(2))).unwrap(), -3);
+
+        let negated = AstNode::Unary(UnaryOp::Not, Box::new(number(5)), Span::default());
+        let either = binary(BinaryOp::Or, negated, AstNode::Bool(true, Span::default()));
+        assert_eq!(evaluate(&either).unwrap(), 1);
+    }
+
+    #[test]
+    fn signedness_follows_the_operand_types() {
+        let one = || AstNode::Constant("ONE".to_string(), 1, Type::U32, Span::default());
+        let wrapped = || binary(BinaryOp::Sub, one(), number(2));
+        assert_eq!(evaluate(&binary(BinaryOp::Lt, wrapped(), number(1))).unwrap(), 0);
+        assert_eq!(evaluate(&binary(BinaryOp::Div, wrapped(), number(2))).unwrap(), 0x7fffffff);
+        assert_eq!(evaluate(&binary(BinaryOp::Shr, wrapped(), number(28))).unwrap(), 15);
+        assert_eq!(evaluate(&binary(BinaryOp::Eq, wrapped(), number(u32::MAX as i64))).unwrap(), 1);
+
+        let minus_one = binary(BinaryOp::Sub, number(1), number(2));
+        assert_eq!(evaluate(&binary(BinaryOp::Lt, minus_one, number(1))).unwrap(), 1);
+        let min = binary(BinaryOp::Shl, number(1), number(31));
+        assert_eq!(evaluate(&binary(BinaryOp::Lt, min, number(0))).unwrap(), 1);
+    }
+
+    #[test]
+    fn rejects_what_cannot_run() {
+        assert_eq!(error_code(&binary(BinaryOp::Mod, number(1), number(0))), Some(E_NOT_CONSTANT));
+        assert_eq!(error_code(&binary(BinaryOp::Shr, number(1), number(32))), Some(E_NOT_CONSTANT));
+        let past_u32 = binary(BinaryOp::Add, number(u32::MAX as i64), number(1));
+        assert_eq!(error_code(&past_u32), Some(E_NOT_CONSTANT));
+    }
+
+    #[test]
+    fn variables_are_not_constant() {
+        let variable = AstNode::Variable("x".to_string(), Span::default());
+        assert_eq!(error_code(&binary(BinaryOp::Add, variable, number(1))), Some(E_NOT_CONSTANT));
+    }
+}
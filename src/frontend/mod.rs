@@ -1,2 +1,7 @@
+pub mod checker;
+pub mod consteval;
 pub mod parser;
-pub mod tokenizer;
\ No newline at end of file
+pub mod resolver;
+pub mod span;
+pub mod tokenizer;
+pub mod types;
\ No newline at end of file
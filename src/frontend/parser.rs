@@ -1,227 +1,1346 @@
+use std::{collections::HashMap, rc::Rc};
+
 use crate::{
-    backend::syscalls::{parse_sys_exit, parse_sys_write, parse_sys_read, parse_sys_open},
-    frontend::tokenizer::Token,
+    backend::syscalls::{SYSCALLS, find_syscall, parse_sys_raw, parse_sys_table},
+    extra::diagnostic::{
+        CompileResult, Diagnostic, E_DUPLICATE_DEFINITION, E_IMMUTABLE_ASSIGNMENT,
+        E_INVALID_LITERAL, E_NOT_CONSTANT, E_UNEXPECTED_TOKEN, E_UNKNOWN_FIELD, E_UNKNOWN_SYSCALL,
+    },
+    frontend::consteval::evaluate,
+    frontend::span::Span,
+    frontend::tokenizer::{SpannedToken, Token},
+    frontend::types::{MAX_ALIGN, StructDef, Type},
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    fn from_token(token: &Token) -> Option<BinaryOp> {
+        Some(match token {
+            Token::Plus => BinaryOp::Add,
+            Token::Minus => BinaryOp::Sub,
+            Token::Star => BinaryOp::Mul,
+            Token::Slash => BinaryOp::Div,
+            Token::Percent => BinaryOp::Mod,
+            Token::Ampersand => BinaryOp::BitAnd,
+            Token::Pipe => BinaryOp::BitOr,
+            Token::Caret => BinaryOp::BitXor,
+            Token::ShiftLeft => BinaryOp::Shl,
+            Token::ShiftRight => BinaryOp::Shr,
+            Token::DoubleEquals => BinaryOp::Eq,
+            Token::NotEquals => BinaryOp::Ne,
+            Token::LessThan => BinaryOp::Lt,
+            Token::LessEquals => BinaryOp::Le,
+            Token::GreaterThan => BinaryOp::Gt,
+            Token::GreaterEquals => BinaryOp::Ge,
+            Token::DoubleAmpersand => BinaryOp::And,
+            Token::DoublePipe => BinaryOp::Or,
+            _ => return None,
+        })
+    }
+
+    /// Binding strength, higher binds tighter. Mirrors Rust's table.
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Gt
+            | BinaryOp::Ge => 3,
+            BinaryOp::BitOr => 4,
+            BinaryOp::BitXor => 5,
+            BinaryOp::BitAnd => 6,
+            BinaryOp::Shl | BinaryOp::Shr => 7,
+            BinaryOp::Add | BinaryOp::Sub => 8,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 9,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    AddressOf,
+    Deref,
+}
+
 #[derive(Debug)]
 pub enum AstNode {
-    Program(Vec<AstNode>),
-    Number(i32),
-    String(String),
-    Identifier(String, i32),
-    FunctionDefinition(String, Vec<AstNode>, Vec<AstNode>),
-    VariableDeclaration(String, Box<AstNode>),
-    
-    // syscall wrappers
-    Syscall(String, Box<AstNode>),
-    Write(Token, Token),
-    Read(usize, String),
-    Open(String, usize, usize),
-    Exit(Token),
+    Program(Vec<AstNode>, Span),
+    Number(i64, Span),
+    Constant(String, i64, Type, Span), // name, folded value, declared type
+    Bool(bool, Span),
+    String(Vec<u8>, Span),
+    Identifier(String, Option<i32>, Type, Span), // buffer name, element count, element type
+    StructDefinition(String, Span), // the layout is part of the `Type::Struct` using it
+    ConstDefinition(String, Span),  // uses are replaced by `Constant` nodes
+    Parameter(String, Type, Span),
+    // name, parameters, return type (`i32` if absent), body
+    FunctionDefinition(String, Vec<AstNode>, Option<Type>, Vec<AstNode>, Span),
+    // name, mutable, type annotation, value
+    VariableDeclaration(String, bool, Option<Type>, Box<AstNode>, Span),
+    Assignment(String, Box<AstNode>, Span),
+    IndexAssignment(String, Box<AstNode>, Box<AstNode>, Span), // buffer name, index, value
+    DerefAssignment(Box<AstNode>, Box<AstNode>, Span),          // pointer, value
+    FieldAssignment(Box<AstNode>, String, Box<AstNode>, Span),  // struct, field, value
+    If(Box<AstNode>, Vec<AstNode>, Vec<AstNode>, Span), // condition, then, else (empty if absent)
+    While(Box<AstNode>, Vec<AstNode>, Span),
+    Loop(Vec<AstNode>, Span),
+    Break(Span),
+    Continue(Span),
+    Return(Option<Box<AstNode>>, Span),
+
+    // expressions
+    Variable(String, Span),
+    Binary(BinaryOp, Box<AstNode>, Box<AstNode>, Span),
+    Unary(UnaryOp, Box<AstNode>, Span),
+    Call(String, Vec<AstNode>, Span),
+    Index(String, Box<AstNode>, Span), // name, index
+    Field(Box<AstNode>, String, Span), // struct or pointer to one, field name
+    Slice(String, Option<Box<AstNode>>, Option<Box<AstNode>>, Span), // name, start, end
+
+    // syscalls
+    Syscall(String, Vec<AstNode>, Span), // wrapper from the syscall table, arguments
+    RawSyscall(Box<AstNode>, Vec<AstNode>, Span), // number, arguments
+}
+
+impl AstNode {
+    pub fn span(&self) -> Span {
+        match self {
+            AstNode::Program(_, span)
+            | AstNode::Number(_, span)
+            | AstNode::Constant(_, _, _, span)
+            | AstNode::Bool(_, span)
+            | AstNode::String(_, span)
+            | AstNode::Identifier(_, _, _, span)
+            | AstNode::StructDefinition(_, span)
+            | AstNode::ConstDefinition(_, span)
+            | AstNode::Parameter(_, _, span)
+            | AstNode::FunctionDefinition(_, _, _, _, span)
+            | AstNode::VariableDeclaration(_, _, _, _, span)
+            | AstNode::Assignment(_, _, span)
+            | AstNode::IndexAssignment(_, _, _, span)
+            | AstNode::DerefAssignment(_, _, span)
+            | AstNode::FieldAssignment(_, _, _, span)
+            | AstNode::If(_, _, _, span)
+            | AstNode::While(_, _, span)
+            | AstNode::Loop(_, span)
+            | AstNode::Break(span)
+            | AstNode::Continue(span)
+            | AstNode::Return(_, span)
+            | AstNode::Variable(_, span)
+            | AstNode::Binary(_, _, _, span)
+            | AstNode::Unary(_, _, span)
+            | AstNode::Call(_, _, span)
+            | AstNode::Index(_, _, span)
+            | AstNode::Field(_, _, span)
+            | AstNode::Slice(_, _, _, span)
+            | AstNode::Syscall(_, _, span)
+            | AstNode::RawSyscall(_, _, span) => *span,
+        }
+    }
 }
 
-pub fn parse(tokens: Vec<Token>) -> AstNode {
+pub fn parse(tokens: Vec<SpannedToken>) -> Result<AstNode, Vec<Diagnostic>> {
     let mut parser = Parser::new(tokens);
-    parser.parse()
+    let program = parser.parse();
+
+    if parser.errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(parser.errors)
+    }
 }
 
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<SpannedToken>,
     current: usize,
+    errors: Vec<Diagnostic>,
+    structs: HashMap<String, Rc<StructDef>>, // declared so far, usable as types below
+    constants: HashMap<String, (i64, Type, Span)>, // value, type and declaration
+    later_constants: HashMap<String, Span>, // every `const` name, to explain early uses
 }
 
 impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+    fn new(tokens: Vec<SpannedToken>) -> Self {
+        // Doc comments only matter to documentation tooling
+        let tokens: Vec<SpannedToken> = tokens
+            .into_iter()
+            .filter(|t| !matches!(t.token, Token::DocComment(_)))
+            .collect();
+
+        let later_constants = tokens
+            .windows(2)
+            .filter_map(|pair| match (&pair[0].token, &pair[1].token) {
+                (Token::Const, Token::Identifier(name)) => Some((name.clone(), pair[1].span)),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            tokens,
+            current: 0,
+            errors: Vec::new(),
+            structs: HashMap::new(),
+            constants: HashMap::new(),
+            later_constants,
+        }
     }
 
     fn parse(&mut self) -> AstNode {
+        let start = self.current_span();
         let mut statements = Vec::new();
 
-        while self.current_token() != Token::EOF {
-            let statement = self.parse_statement();
-            statements.push(statement);
+        while self.current_token() != Token::Eof {
+            match self.parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    let position = self.current;
+                    self.recover(*error);
+
+                    // A stray `}` has no block to close at the top level
+                    if self.current == position && self.current_token() == Token::CurlyClose {
+                        self.current += 1;
+                    }
+                }
+            }
         }
 
-        AstNode::Program(statements)
+        AstNode::Program(statements, start.to(self.current_span()))
+    }
+
+    /// Records a syntax error and skips ahead to the next statement boundary:
+    /// just past a `;`, just past the `}` closing a block opened while
+    /// skipping, or just before a `}` closing the enclosing block.
+    fn recover(&mut self, error: Diagnostic) {
+        self.errors.push(error);
+
+        let mut depth = 0;
+        loop {
+            match self.current_token() {
+                Token::Eof => break,
+                Token::Semicolon if depth == 0 => {
+                    self.current += 1;
+                    break;
+                }
+                Token::CurlyOpen => depth += 1,
+                Token::CurlyClose if depth == 0 => break,
+                Token::CurlyClose => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.current += 1;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            self.current += 1;
+        }
     }
 
     pub fn current_token(&self) -> Token {
-        self.tokens[self.current].clone()
+        self.tokens[self.current].token.clone()
+    }
+
+    /// The token after the current one, without consuming anything.
+    fn peek_token(&self) -> Token {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token.clone(),
+            None => Token::Eof,
+        }
+    }
+
+    pub fn current_span(&self) -> Span {
+        self.tokens[self.current].span
     }
 
-    fn parse_function_definition(&mut self) -> AstNode {
-        self.consume(Token::Function);
+    /// Span from `start` up to the end of the last consumed token.
+    pub fn span_from(&self, start: Span) -> Span {
+        match self.current.checked_sub(1) {
+            Some(prev) => start.to(self.tokens[prev].span),
+            None => start,
+        }
+    }
+
+    /// "expected X, found Y" error pointing at the current token.
+    pub fn unexpected(&self, expected: &str) -> Diagnostic {
+        Diagnostic::error(
+            E_UNEXPECTED_TOKEN,
+            format!("expected {}, found {}", expected, self.current_token()),
+            self.current_span(),
+        )
+        .with_label(format!("expected {}", expected))
+    }
 
-        let identifier = self.consume_identifier();
+    fn parse_function_definition(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::Function)?;
 
-        self.consume(Token::ParentOpen);
+        let identifier = self.consume_identifier()?;
+
+        self.consume(Token::ParentOpen)?;
 
         let mut parameters = Vec::new();
         while self.current_token() != Token::ParentClose {
-            let parameter = self.parse_parameter();
+            let parameter = self.parse_parameter()?;
             parameters.push(parameter);
 
             if self.current_token() == Token::Comma {
-                self.consume(Token::Comma);
+                self.consume(Token::Comma)?;
             }
         }
-        self.consume(Token::ParentClose);
+        self.consume(Token::ParentClose)?;
+
+        let return_type = if self.current_token() == Token::Arrow {
+            self.consume(Token::Arrow)?;
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
 
-        self.consume(Token::CurlyOpen);
+        let body = self.parse_block()?;
+
+        Ok(AstNode::FunctionDefinition(
+            identifier,
+            parameters,
+            return_type,
+            body,
+            self.span_from(start),
+        ))
+    }
+
+    /// Parses `{ statements }`, recovering from errors inside the block so
+    /// that every broken statement gets reported.
+    fn parse_block(&mut self) -> CompileResult<Vec<AstNode>> {
+        let open = self.current_span();
+        self.consume(Token::CurlyOpen)?;
 
         let mut body = Vec::new();
         while self.current_token() != Token::CurlyClose {
-            let statement = self.parse_statement();
-            body.push(statement);
+            if self.current_token() == Token::Eof {
+                return Err(self
+                    .unexpected("`}`")
+                    .with_secondary(open, "this block is never closed")
+                    .into());
+            }
+
+            match self.parse_statement() {
+                Ok(statement) => body.push(statement),
+                Err(error) => self.recover(*error),
+            }
         }
-        self.consume(Token::CurlyClose);
+        self.consume(Token::CurlyClose)?;
 
-        AstNode::FunctionDefinition(identifier, parameters, body)
+        Ok(body)
     }
 
-    fn parse_syscall(&mut self, syscall: String) -> AstNode {
-        let matched_syscall = match syscall.as_str() {
-            "write" => parse_sys_write(self),
-            "read" => parse_sys_read(self),
-            "exit" => parse_sys_exit(self),
-            "open" => parse_sys_open(self),
-            _ => {
-                panic!("Unknown syscall: {}", syscall);
+    fn parse_syscall(&mut self, syscall: String) -> CompileResult<AstNode> {
+        if syscall == "syscall" {
+            return parse_sys_raw(self);
+        }
+
+        match find_syscall(&syscall) {
+            Some(spec) => parse_sys_table(self, spec),
+            None => {
+                let supported: Vec<String> = SYSCALLS
+                    .iter()
+                    .map(|spec| format!("`${}`", spec.name))
+                    .collect();
+
+                Err(Diagnostic::error(
+                    E_UNKNOWN_SYSCALL,
+                    format!("unknown syscall `${}`", syscall),
+                    self.current_span(),
+                )
+                .with_label("not a known syscall wrapper")
+                .with_note(format!("supported syscalls are {}", supported.join(", ")))
+                .with_note("any other syscall can be made with `$syscall(number, args...)`")
+                .into())
+            }
+        }
+    }
+
+    fn parse_variable_declaration(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::Let)?;
+
+        let mutable = self.current_token() == Token::Mut;
+        if mutable {
+            self.consume(Token::Mut)?;
+        }
+
+        let name_span = self.current_span();
+        let identifier = self.consume_identifier()?;
+        self.check_not_constant(&identifier, name_span)?;
+
+        let annotation = if self.current_token() == Token::Colon {
+            self.consume(Token::Colon)?;
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        self.consume(Token::Equals)?;
+
+        let value = self.parse_expression()?;
+
+        self.consume(Token::Semicolon)?;
+
+        Ok(AstNode::VariableDeclaration(
+            identifier,
+            mutable,
+            annotation,
+            Box::new(value),
+            self.span_from(start),
+        ))
+    }
+
+    /// `name = value;`, `name[index] = value;` or a field of either,
+    /// e.g. `name.field = value;` or `name[index].field = value;`.
+    fn parse_assignment(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        let identifier = self.consume_identifier()?;
+        if let Some((_, _, declared)) = self.constants.get(&identifier) {
+            return Err(Diagnostic::error(
+                E_IMMUTABLE_ASSIGNMENT,
+                format!("cannot assign to constant `{}`", identifier),
+                start,
+            )
+            .with_label("cannot assign")
+            .with_secondary(*declared, "declared here")
+            .into());
+        }
+
+        let mut target = if self.current_token() == Token::BracketOpen {
+            self.consume(Token::BracketOpen)?;
+            let index = self.parse_expression()?;
+            self.consume(Token::BracketClose)?;
+            AstNode::Index(identifier, Box::new(index), self.span_from(start))
+        } else {
+            AstNode::Variable(identifier, start)
+        };
+        target = self.parse_fields(target, start)?;
+
+        self.consume(Token::Equals)?;
+
+        let value = self.parse_expression()?;
+
+        self.consume(Token::Semicolon)?;
+
+        let (value, span) = (Box::new(value), self.span_from(start));
+        Ok(match target {
+            AstNode::Variable(name, _) => AstNode::Assignment(name, value, span),
+            AstNode::Index(name, index, _) => AstNode::IndexAssignment(name, index, value, span),
+            AstNode::Field(base, field, _) => AstNode::FieldAssignment(base, field, value, span),
+            _ => unreachable!(),
+        })
+    }
+
+    /// `struct Name { field: type, ... }`, optionally marked `packed` (no
+    /// padding between fields) and/or `align(N)` before the body. The name
+    /// is usable behind a pointer inside the body, e.g. `next: *Name`.
+    fn parse_struct_definition(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::Struct)?;
+
+        let name_span = self.current_span();
+        let name = self.consume_identifier()?;
+        if Type::named(&name).is_some() || self.structs.contains_key(&name) {
+            return Err(Diagnostic::error(
+                E_DUPLICATE_DEFINITION,
+                format!("type `{}` is defined multiple times", name),
+                name_span,
+            )
+            .with_label("redefined here")
+            .into());
+        }
+
+        let mut packed = false;
+        let mut align = None;
+        loop {
+            match self.current_token() {
+                Token::Identifier(attribute) if attribute == "packed" => {
+                    self.consume(Token::Identifier(attribute))?;
+                    packed = true;
+                }
+                Token::Identifier(attribute) if attribute == "align" => {
+                    let align_span = self.current_span();
+                    self.consume(Token::Identifier(attribute))?;
+                    self.consume(Token::ParentOpen)?;
+                    let value = self.parse_constant()?;
+                    self.consume(Token::ParentClose)?;
+
+                    if value <= 0 || value.count_ones() != 1 || value > MAX_ALIGN as i64 {
+                        let label = if value > MAX_ALIGN as i64 {
+                            "stack buffers are aligned to at most 8 bytes"
+                        } else {
+                            "not a power of two"
+                        };
+                        return Err(Diagnostic::error(
+                            E_UNEXPECTED_TOKEN,
+                            format!("invalid alignment `{}`", value),
+                            self.span_from(align_span),
+                        )
+                        .with_label(label)
+                        .into());
+                    }
+                    align = Some(value as i32);
+                }
+                _ => break,
+            }
+        }
+
+        self.consume(Token::CurlyOpen)?;
+        let def = Rc::new(StructDef::declare(&name));
+        self.structs.insert(name.clone(), def.clone());
+
+        // A broken field doesn't end the struct, the rest is still checked
+        let mut fields: Vec<(String, Type)> = Vec::new();
+        while !matches!(self.current_token(), Token::CurlyClose | Token::Eof) {
+            match self.parse_field(&name, &fields) {
+                Ok(Some(field)) => fields.push(field),
+                Ok(None) => {}
+                Err(error) => self.recover_field(*error),
             }
+        }
+
+        def.lay_out(fields, packed, align);
+        self.consume(Token::CurlyClose)?;
+
+        Ok(AstNode::StructDefinition(name, self.span_from(start)))
+    }
+
+    /// `name: type` inside the body of struct `owner`, and the `,` after it
+    /// unless it's the last field. A field that parses but can't be used is
+    /// reported right away and left out.
+    fn parse_field(
+        &mut self,
+        owner: &str,
+        fields: &[(String, Type)],
+    ) -> CompileResult<Option<(String, Type)>> {
+        let field_span = self.current_span();
+        let field = self.consume_identifier()?;
+        self.consume(Token::Colon)?;
+        let type_start = self.current_span();
+        let ty = self.parse_type()?;
+        let type_span = self.span_from(type_start);
+
+        if self.current_token() != Token::CurlyClose {
+            self.consume(Token::Comma)?;
+        }
+
+        let error = if fields.iter().any(|(existing, _)| *existing == field) {
+            Diagnostic::error(
+                E_DUPLICATE_DEFINITION,
+                format!("field `{}` is declared multiple times in `{}`", field, owner),
+                field_span,
+            )
+            .with_label("declared again here")
+        } else if ty == Type::Str {
+            Diagnostic::error(
+                E_UNEXPECTED_TOKEN,
+                format!("field `{}` can't be of type `str`", field),
+                field_span,
+            )
+            .with_label("strings only live in read-only data")
+            .with_note("use `*u8` for the address of a string")
+        } else if let Some(incomplete) = incomplete_struct(&ty) {
+            Diagnostic::error(
+                E_UNEXPECTED_TOKEN,
+                format!("field `{}` would contain `{}` itself", field, incomplete.name),
+                type_span,
+            )
+            .with_label("a struct can't contain itself")
+            .with_note(format!("point to it with `*{}` instead", incomplete.name))
+        } else {
+            return Ok(Some((field, ty)));
         };
 
-        AstNode::Syscall(syscall, Box::new(matched_syscall))
+        self.errors.push(error);
+        Ok(None)
+    }
+
+    /// Records an error inside a struct body and skips to the next field:
+    /// just past a `,`, or just before the `}` closing the body.
+    fn recover_field(&mut self, error: Diagnostic) {
+        self.errors.push(error);
+
+        while !matches!(self.current_token(), Token::CurlyClose | Token::Eof) {
+            let token = self.current_token();
+            self.current += 1;
+            if token == Token::Comma {
+                break;
+            }
+        }
     }
 
-    fn parse_variable_declaration(&mut self) -> AstNode {
-        self.consume(Token::Let);
+    /// `const NAME: type = value;`. The value is evaluated right away, so
+    /// the constant can be used below wherever a number is expected, buffer
+    /// sizes included.
+    fn parse_const_definition(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::Const)?;
 
-        let identifier = self.consume_identifier();
+        let name_span = self.current_span();
+        let name = self.consume_identifier()?;
+        self.consume(Token::Colon)?;
+        let type_start = self.current_span();
+        let ty = self.parse_type()?;
+        let type_span = self.span_from(type_start);
+        self.consume(Token::Equals)?;
+        let value_span = self.current_span();
+        let value = self.parse_constant()?;
 
-        self.consume(Token::Equals);
+        // Checked before the `;`, so recovery only skips this definition
+        if !ty.is_integer() && ty != Type::Bool {
+            return Err(Diagnostic::error(
+                E_UNEXPECTED_TOKEN,
+                format!("constants can't be of type `{}`", ty),
+                type_span,
+            )
+            .with_label("not an integer or `bool`")
+            .into());
+        }
+        let fits = if ty == Type::Bool { (0..=1).contains(&value) } else { ty.fits(value) };
+        if !fits {
+            return Err(Diagnostic::error(
+                E_INVALID_LITERAL,
+                format!("constant `{}` doesn't fit in `{}`", name, ty),
+                self.span_from(value_span),
+            )
+            .with_label(format!("evaluates to {}", value))
+            .into());
+        }
 
-        let value: AstNode = match self.current_token() {
-            Token::Number(_) | Token::String(_) => self.parse_datatype(),
-            Token::Syscall(sys) => self.parse_syscall(sys),
-            _ => panic!("Unsupported value in variable declaration: {:?}", self.current_token()),
+        self.check_not_constant(&name, name_span)?;
+        self.consume(Token::Semicolon)?;
+        self.constants.insert(name.clone(), (value, ty, name_span));
+
+        Ok(AstNode::ConstDefinition(name, self.span_from(start)))
+    }
+
+    /// An expression that has to be known at compile time, e.g. a buffer size.
+    fn parse_constant(&mut self) -> CompileResult<i64> {
+        let expr = self.parse_expression()?;
+        evaluate(&expr)
+    }
+
+    /// Constants are replaced by their value wherever they are used, so no
+    /// other constant, variable, parameter or buffer may take their name.
+    fn check_not_constant(&self, name: &str, span: Span) -> CompileResult<()> {
+        match self.constants.get(name) {
+            Some((_, _, declared)) => Err(Diagnostic::error(
+                E_DUPLICATE_DEFINITION,
+                format!("`{}` is already declared as a constant", name),
+                span,
+            )
+            .with_label("declared again here")
+            .with_secondary(*declared, "constant declared here")
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// A count that has to be known at compile time: a buffer size or the
+    /// length of an array type.
+    fn parse_count(&mut self, what: &str) -> CompileResult<i32> {
+        let span = self.current_span();
+        let value = self.parse_constant()?;
+        match i32::try_from(value) {
+            Ok(count) if count >= 0 => Ok(count),
+            _ => Err(Diagnostic::error(
+                E_NOT_CONSTANT,
+                format!("invalid {} `{}`", what, value),
+                self.span_from(span),
+            )
+            .with_label("expected a count from 0 up")
+            .into()),
+        }
+    }
+
+    /// `*pointer = value;` where the pointer is any unary expression, e.g.
+    /// `*p`, `*(p + 1)` or `**pp`.
+    fn parse_deref_assignment(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::Star)?;
+        let pointer = self.parse_unary()?;
+        self.consume(Token::Equals)?;
+        let value = self.parse_expression()?;
+        self.consume(Token::Semicolon)?;
+        Ok(AstNode::DerefAssignment(
+            Box::new(pointer),
+            Box::new(value),
+            self.span_from(start),
+        ))
+    }
+
+    /// `buf[N] name;` reserves N bytes, `buf[N] name: T;` N elements of `T`
+    /// and `buf name: T;` a single struct.
+    fn parse_buffer_declaration(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::Buf)?;
+
+        let size = if self.current_token() == Token::BracketOpen {
+            self.consume(Token::BracketOpen)?;
+            let size = self.parse_count("buffer size")?;
+            self.consume(Token::BracketClose)?;
+            Some(size)
+        } else {
+            None
+        };
+
+        let name_span = self.current_span();
+        let identifier = self.consume_identifier()?;
+        self.check_not_constant(&identifier, name_span)?;
+
+        let element = if self.current_token() == Token::Colon || size.is_none() {
+            self.consume(Token::Colon)?;
+            self.parse_type()?
+        } else {
+            Type::U8
         };
 
-        self.consume(Token::Semicolon);
+        self.consume(Token::Semicolon)?;
 
-        AstNode::VariableDeclaration(identifier, Box::new(value))
+        Ok(AstNode::Identifier(identifier, size, element, self.span_from(start)))
     }
 
-    fn parse_buffer_declaration(&mut self) -> AstNode {
-        self.consume(Token::Buf);
-        self.consume(Token::BracketOpen);
+    /// `if cond { .. } else if cond { .. } else { .. }`; an `else if` chain is
+    /// stored as a nested `If` inside the else branch.
+    fn parse_if(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::If)?;
+
+        let condition = self.parse_expression()?;
+        let then_body = self.parse_block()?;
+
+        let else_body = if self.current_token() == Token::Else {
+            self.consume(Token::Else)?;
 
-        let bufsize_token = self.current_token();
-        let size = if let Token::Number(n) = bufsize_token {
-            self.consume(bufsize_token.clone());
-            n
+            match self.current_token() {
+                Token::If => vec![self.parse_if()?],
+                Token::CurlyOpen => self.parse_block()?,
+                _ => return Err(self.unexpected("`{` or `if`").into()),
+            }
         } else {
-            panic!(
-                "Expected a number for buffer size, found: {:?}",
-                bufsize_token
-            );
+            Vec::new()
         };
 
-        self.consume(Token::BracketClose);
+        Ok(AstNode::If(
+            Box::new(condition),
+            then_body,
+            else_body,
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_while(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::While)?;
+
+        let condition = self.parse_expression()?;
+        let body = self.parse_block()?;
 
-        let identifier = self.consume_identifier();
+        Ok(AstNode::While(
+            Box::new(condition),
+            body,
+            self.span_from(start),
+        ))
+    }
+
+    fn parse_loop(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::Loop)?;
+
+        let body = self.parse_block()?;
+
+        Ok(AstNode::Loop(body, self.span_from(start)))
+    }
 
-        self.consume(Token::Semicolon);
+    /// `break;` or `continue;`
+    fn parse_loop_control(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        let token = self.current_token();
+        self.consume(token.clone())?;
+        self.consume(Token::Semicolon)?;
 
-        AstNode::Identifier(identifier, size)
+        let span = self.span_from(start);
+        Ok(match token {
+            Token::Break => AstNode::Break(span),
+            _ => AstNode::Continue(span),
+        })
     }
 
+    /// `return;` or `return expr;`
+    fn parse_return(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        self.consume(Token::Return)?;
+
+        let value = if self.current_token() == Token::Semicolon {
+            None
+        } else {
+            Some(Box::new(self.parse_expression()?))
+        };
+
+        self.consume(Token::Semicolon)?;
+
+        Ok(AstNode::Return(value, self.span_from(start)))
+    }
 
-    fn parse_statement(&mut self) -> AstNode {
-        let ast_node = match self.current_token() {
+    fn parse_statement(&mut self) -> CompileResult<AstNode> {
+        match self.current_token() {
             Token::Function => self.parse_function_definition(),
-            Token::Syscall(syscall) => { 
-                let node = self.parse_syscall(syscall);
-                
-                self.consume(Token::Semicolon);
-                node
-            },
+            Token::Syscall(syscall) => {
+                let node = self.parse_syscall(syscall)?;
+
+                self.consume(Token::Semicolon)?;
+                Ok(node)
+            }
             Token::Let => self.parse_variable_declaration(),
+            Token::Identifier(_) if self.peek_token() == Token::ParentOpen => {
+                let call = self.parse_primary()?;
+
+                self.consume(Token::Semicolon)?;
+                Ok(call)
+            }
+            Token::Identifier(_) => self.parse_assignment(),
+            Token::Star => self.parse_deref_assignment(),
             Token::Buf => self.parse_buffer_declaration(),
+            Token::Struct => self.parse_struct_definition(),
+            Token::Const => self.parse_const_definition(),
+            Token::If => self.parse_if(),
+            Token::While => self.parse_while(),
+            Token::Loop => self.parse_loop(),
+            Token::Break | Token::Continue => self.parse_loop_control(),
+            Token::Return => self.parse_return(),
+            _ => Err(self.unexpected("a statement").into()),
+        }
+    }
+
+    pub fn parse_expression(&mut self) -> CompileResult<AstNode> {
+        self.parse_binary(0)
+    }
+
+    /// Precedence climbing: parses operators binding at least as tightly as
+    /// `min_precedence`, all of them left-associative.
+    fn parse_binary(&mut self, min_precedence: u8) -> CompileResult<AstNode> {
+        let mut lhs = self.parse_unary()?;
+
+        while let Some(op) = BinaryOp::from_token(&self.current_token()) {
+            let precedence = op.precedence();
+            if precedence < min_precedence {
+                break;
+            }
+            self.consume(self.current_token())?;
+
+            let rhs = self.parse_binary(precedence + 1)?;
+            let span = lhs.span().to(rhs.span());
+            lhs = AstNode::Binary(op, Box::new(lhs), Box::new(rhs), span);
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        let op = match self.current_token() {
+            Token::Minus => UnaryOp::Neg,
+            Token::Bang => UnaryOp::Not,
+            Token::Ampersand => UnaryOp::AddressOf,
+            Token::Star => UnaryOp::Deref,
             _ => {
-                panic!("Expected a statement, found: {:?}", self.current_token())
+                let primary = self.parse_primary()?;
+                return self.parse_fields(primary, start);
             }
         };
+        self.consume(self.current_token())?;
 
-        ast_node
+        let operand = self.parse_unary()?;
+        Ok(AstNode::Unary(op, Box::new(operand), self.span_from(start)))
     }
 
-    fn parse_datatype(&mut self) -> AstNode {
+    fn parse_primary(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
         match self.current_token() {
-            Token::Number(number) => {
-                self.consume(Token::Number(number));
-                AstNode::Number(number)
+            Token::Number(_) | Token::String(_) => self.parse_datatype(),
+            Token::True | Token::False => {
+                let value = self.current_token() == Token::True;
+                self.consume(self.current_token())?;
+                Ok(AstNode::Bool(value, start))
             }
-            Token::String(string) => {
-                self.consume(Token::String(string.clone()));
-                AstNode::String(string.clone())
+            Token::Identifier(name) => {
+                self.consume(Token::Identifier(name.clone()))?;
+                match self.current_token() {
+                    Token::ParentOpen => self.parse_call_arguments(name, start),
+                    Token::BracketOpen => self.parse_subscript(name, start),
+                    _ => match self.constants.get(&name) {
+                        Some((value, ty, _)) => {
+                            Ok(AstNode::Constant(name, *value, ty.clone(), start))
+                        }
+                        None => match self.later_constants.get(&name) {
+                            Some(declared) => Err(Diagnostic::error(
+                                E_NOT_CONSTANT,
+                                format!("constant `{}` is used before its definition", name),
+                                start,
+                            )
+                            .with_label("used here")
+                            .with_secondary(*declared, "defined here")
+                            .with_note("constants are defined in order, move the `const` up")
+                            .into()),
+                            None => Ok(AstNode::Variable(name, start)),
+                        },
+                    },
+                }
             }
-            _ => {
-                panic!("Expected a datatype, found: {:?}", self.current_token())
+            // Every syscall evaluates to whatever the kernel leaves in r0
+            Token::Syscall(name) => self.parse_syscall(name),
+            Token::Sizeof | Token::Offsetof => self.parse_layout_query(),
+            Token::ParentOpen => {
+                self.consume(Token::ParentOpen)?;
+                let expr = self.parse_expression()?;
+                self.consume(Token::ParentClose)?;
+                Ok(expr)
             }
+            _ => Err(self.unexpected("an expression").into()),
         }
     }
 
-    fn parse_parameter(&mut self) -> AstNode {
-        match self.current_token() {
-            Token::Identifier(_) => self.consume_sized_identifier(),
+    /// Any number of `.field` accesses following `base`.
+    fn parse_fields(&mut self, mut base: AstNode, start: Span) -> CompileResult<AstNode> {
+        while self.current_token() == Token::Dot {
+            self.consume(Token::Dot)?;
+            let field = self.consume_identifier()?;
+            base = AstNode::Field(Box::new(base), field, self.span_from(start));
+        }
+
+        Ok(base)
+    }
+
+    /// `sizeof(T)` or `offsetof(T, field)`, folded into a number right away.
+    fn parse_layout_query(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        let is_sizeof = self.current_token() == Token::Sizeof;
+        self.consume(self.current_token())?;
+        self.consume(Token::ParentOpen)?;
+
+        let type_span = self.current_span();
+        let ty = self.parse_type()?;
+        if let Some(incomplete) = incomplete_struct(&ty) {
+            return Err(Diagnostic::error(
+                E_UNEXPECTED_TOKEN,
+                format!("the layout of `{}` isn't known yet", incomplete.name),
+                self.span_from(type_span),
+            )
+            .with_label("still being declared")
+            .into());
+        }
+        let value = if is_sizeof {
+            ty.size()
+        } else {
+            self.consume(Token::Comma)?;
+            let field_span = self.current_span();
+            let field = self.consume_identifier()?;
+
+            let def = match &ty {
+                Type::Struct(def) => def,
+                _ => {
+                    return Err(Diagnostic::error(
+                        E_UNKNOWN_FIELD,
+                        format!("`{}` has no fields", ty),
+                        type_span,
+                    )
+                    .with_label("not a struct")
+                    .into());
+                }
+            };
+            match def.field(&field) {
+                Some(field) => field.offset,
+                None => return Err(unknown_field(def, &field, field_span).into()),
+            }
+        };
+
+        self.consume(Token::ParentClose)?;
+        Ok(AstNode::Number(value as i64, self.span_from(start)))
+    }
+
+    /// `(expr, expr, ...)` following the name of a called function.
+    fn parse_call_arguments(&mut self, name: String, start: Span) -> CompileResult<AstNode> {
+        self.consume(Token::ParentOpen)?;
+
+        let mut arguments = Vec::new();
+        while self.current_token() != Token::ParentClose {
+            arguments.push(self.parse_expression()?);
+
+            if self.current_token() != Token::ParentClose {
+                self.consume(Token::Comma)?;
+            }
+        }
+        self.consume(Token::ParentClose)?;
+
+        Ok(AstNode::Call(name, arguments, self.span_from(start)))
+    }
+
+    /// `[index]` or `[start..end]` following the name of a string or buffer.
+    /// Either bound of a slice may be left out: `buf[..n]` starts at 0 and
+    /// `buf[n..]` runs to the end.
+    fn parse_subscript(&mut self, name: String, start: Span) -> CompileResult<AstNode> {
+        self.consume(Token::BracketOpen)?;
+
+        let from = match self.current_token() {
+            Token::DotDot => None,
             _ => {
-                panic!(
-                    "Expected a parameter identifier, found: {:?}",
-                    self.current_token()
-                )
+                let index = self.parse_expression()?;
+                if self.current_token() == Token::BracketClose {
+                    self.consume(Token::BracketClose)?;
+                    return Ok(AstNode::Index(name, Box::new(index), self.span_from(start)));
+                }
+                Some(Box::new(index))
             }
+        };
+        self.consume(Token::DotDot)?;
+        let to = match self.current_token() {
+            Token::BracketClose => None,
+            _ => Some(Box::new(self.parse_expression()?)),
+        };
+        self.consume(Token::BracketClose)?;
+
+        Ok(AstNode::Slice(name, from, to, self.span_from(start)))
+    }
+
+    fn parse_datatype(&mut self) -> CompileResult<AstNode> {
+        let span = self.current_span();
+        match self.current_token() {
+            Token::Number(number) => {
+                self.consume(Token::Number(number))?;
+                Ok(AstNode::Number(number, span))
+            }
+            Token::String(string) => {
+                self.consume(Token::String(string.clone()))?;
+                Ok(AstNode::String(string, span))
+            }
+            _ => Err(self.unexpected("a number or string").into()),
         }
     }
 
-    fn consume_sized_identifier(&mut self) -> AstNode {
-        let identifier = self.consume_identifier();
+    /// `name: type`. A plain byte count such as `n: 4` is still accepted and
+    /// means a signed integer of that width.
+    fn parse_parameter(&mut self) -> CompileResult<AstNode> {
+        let start = self.current_span();
+        let identifier = match self.current_token() {
+            Token::Identifier(_) => self.consume_identifier()?,
+            _ => return Err(self.unexpected("a parameter name").into()),
+        };
+        self.check_not_constant(&identifier, start)?;
 
-        self.consume(Token::Colon);
+        self.consume(Token::Colon)?;
 
-        let size = if let Token::Number(size) = self.current_token() {
-            self.consume(Token::Number(size));
-            size
-        } else {
-            panic!(
-                "Expected size after identifier, found: {:?}",
-                self.current_token()
-            );
+        let ty = match self.current_token() {
+            Token::Number(size) => {
+                let size_span = self.current_span();
+                self.consume(Token::Number(size))?;
+                match size {
+                    1 => Type::I8,
+                    2 => Type::I16,
+                    4 => Type::I32,
+                    _ => {
+                        return Err(Diagnostic::error(
+                            E_UNEXPECTED_TOKEN,
+                            format!("parameter `{}` can't be {} bytes wide", identifier, size),
+                            size_span,
+                        )
+                        .with_label("expected 1, 2 or 4")
+                        .with_note("write a type such as `u32` instead")
+                        .into());
+                    }
+                }
+            }
+            _ => self.parse_type()?,
         };
 
-        AstNode::Identifier(identifier, size)
+        Ok(AstNode::Parameter(identifier, ty, self.span_from(start)))
+    }
+
+    /// A type: `i8`, `u8`, `i16`, `u16`, `i32`, `u32`, `bool`, `str`, a
+    /// pointer `*T` or an array `[T; N]`.
+    pub fn parse_type(&mut self) -> CompileResult<Type> {
+        match self.current_token() {
+            Token::Star => {
+                self.consume(Token::Star)?;
+                Ok(Type::Ptr(Box::new(self.parse_type()?)))
+            }
+            Token::BracketOpen => {
+                self.consume(Token::BracketOpen)?;
+                let element = self.parse_type()?;
+                self.consume(Token::Semicolon)?;
+                let len = self.parse_count("array length")?;
+                self.consume(Token::BracketClose)?;
+                Ok(Type::Array(Box::new(element), len))
+            }
+            Token::Identifier(name) => {
+                let ty = Type::named(&name)
+                    .or_else(|| self.structs.get(&name).map(|def| Type::Struct(def.clone())));
+                match ty {
+                    Some(ty) => {
+                        self.consume(Token::Identifier(name))?;
+                        Ok(ty)
+                    }
+                    None => Err(Diagnostic::error(
+                        E_UNEXPECTED_TOKEN,
+                        format!("unknown type `{}`", name),
+                        self.current_span(),
+                    )
+                    .with_label("not a type")
+                    .with_note(
+                        "types are i8, u8, i16, u16, i32, u32, bool, str, *T, [T; N] \
+                         and structs declared further up",
+                    )
+                    .into()),
+                }
+            }
+            _ => Err(self.unexpected("a type").into()),
+        }
     }
 
-    fn consume_identifier(&mut self) -> String {
+    fn consume_identifier(&mut self) -> CompileResult<String> {
         if let Token::Identifier(id) = self.current_token() {
-            self.consume(Token::Identifier(id.clone()));
-            id
+            self.consume(Token::Identifier(id.clone()))?;
+            Ok(id)
         } else {
-            panic!("Expected identifier, found: {:?}", self.current_token());
+            Err(self.unexpected("an identifier").into())
         }
     }
 
-    pub fn consume(&mut self, token: Token) -> Token {
+    pub fn consume(&mut self, token: Token) -> CompileResult<Token> {
         if self.current_token() == token {
-            let consumed = self.tokens[self.current].clone();
+            let consumed = self.current_token();
             self.current += 1;
-            consumed
+            Ok(consumed)
         } else {
-            panic!(
-                "Expected token {:?}, found {:?}",
-                token,
-                self.current_token()
-            );
+            Err(self.unexpected(&token.to_string()).into())
+        }
+    }
+}
+
+/// `field` doesn't exist in `def`.
+pub fn unknown_field(def: &StructDef, field: &str, span: Span) -> Diagnostic {
+    let fields: Vec<&str> = def.fields().iter().map(|field| field.name.as_str()).collect();
+    Diagnostic::error(
+        E_UNKNOWN_FIELD,
+        format!("no field `{}` in struct `{}`", field, def.name),
+        span,
+    )
+    .with_label("unknown field")
+    .with_note(format!("the fields are: {}", fields.join(", ")))
+}
+
+/// The struct in `ty` that is still being declared, unless it's only
+/// pointed to.
+fn incomplete_struct(ty: &Type) -> Option<&StructDef> {
+    match ty {
+        Type::Struct(def) if !def.is_complete() => Some(def),
+        Type::Array(element, _) => incomplete_struct(element),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{extra::diagnostic::E_ARGUMENT_COUNT, frontend::tokenizer::tokenize};
+
+    /// Codes of the errors reported for `source`, in order.
+    fn codes(source: &str) -> Vec<&'static str> {
+        match parse(tokenize(source).unwrap()) {
+            Ok(_) => Vec::new(),
+            Err(diagnostics) => diagnostics.iter().map(|d| d.code).collect(),
+        }
+    }
+
+    #[test]
+    fn reports_every_broken_statement() {
+        let source = "fn main() { let = 1; let y = ; buf[4] b; $exit(0 } fn f() { $exit(1); }";
+        assert_eq!(codes(source), [E_UNEXPECTED_TOKEN; 3]);
+    }
+
+    #[test]
+    fn recovers_at_the_top_level() {
+        let source = "= 1; } fn main() { $exit(0); } fn () { }";
+        assert_eq!(codes(source), [E_UNEXPECTED_TOKEN; 3]);
+    }
+
+    #[test]
+    fn unclosed_block() {
+        let errors = parse(tokenize("fn main() { $exit(0);").unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].secondary[0].message, "this block is never closed");
+    }
+
+    /// `source` parsed as the value of a `let`, written out as s-expressions.
+    fn expression(source: &str) -> String {
+        fn render(node: &AstNode) -> String {
+            match node {
+                AstNode::Binary(op, lhs, rhs, _) => {
+                    format!("({:?} {} {})", op, render(lhs), render(rhs))
+                }
+                AstNode::Unary(op, operand, _) => format!("({:?} {})", op, render(operand)),
+                AstNode::Number(n, _) => n.to_string(),
+                AstNode::Variable(name, _) => name.clone(),
+                other => format!("{:?}", other),
+            }
         }
+
+        let program = parse(tokenize(&format!("let x = {};", source)).unwrap()).unwrap();
+        let AstNode::Program(statements, _) = program else { unreachable!() };
+        let AstNode::VariableDeclaration(.., value, _) = &statements[0] else { unreachable!() };
+        render(value)
+    }
+
+    #[test]
+    fn operator_precedence() {
+        assert_eq!(expression("1 + 2 * 3"), "(Add 1 (Mul 2 3))");
+        assert_eq!(expression("a << 1 + 2 & b"), "(BitAnd (Shl a (Add 1 2)) b)");
+        assert_eq!(expression("a | b ^ c & d"), "(BitOr a (BitXor b (BitAnd c d)))");
+        assert_eq!(expression("a < b == c && d || e"), "(Or (And (Eq (Lt a b) c) d) e)");
+        assert_eq!(expression("(1 + 2) * -a"), "(Mul (Add 1 2) (Neg a))");
+    }
+
+    #[test]
+    fn operators_are_left_associative() {
+        assert_eq!(expression("a - b - c"), "(Sub (Sub a b) c)");
+        assert_eq!(expression("a / b % c"), "(Mod (Div a b) c)");
+        assert_eq!(expression("!!a"), "(Not (Not a))");
+    }
+
+    #[test]
+    fn raw_syscall_operands() {
+        assert!(codes("fn main() { $syscall(4, 1, 2, 3, 4, 5, 6, 7); }").is_empty());
+        let source = "fn main() { $syscall(); $syscall(4, 1, 2, 3, 4, 5, 6, 7, 8); }";
+        assert_eq!(codes(source), [E_UNEXPECTED_TOKEN, E_ARGUMENT_COUNT]);
+    }
+
+    #[test]
+    fn recovers_inside_struct_bodies() {
+        let source = "struct p { x: i32 y: i32, z: , w: u8 } fn main() { $exit(sizeof(p)); }";
+        assert_eq!(codes(source), [E_UNEXPECTED_TOKEN, E_UNEXPECTED_TOKEN]);
+    }
+
+    #[test]
+    fn keeps_parsing_fields_after_invalid_ones() {
+        let source = "struct p { x: i32, x: u8, s: str, me: p, y: u16 } fn main() { let n = 1 }";
+        assert_eq!(
+            codes(source),
+            [E_DUPLICATE_DEFINITION, E_UNEXPECTED_TOKEN, E_UNEXPECTED_TOKEN, E_UNEXPECTED_TOKEN]
+        );
+    }
+
+    #[test]
+    fn structs_can_point_to_themselves() {
+        assert!(codes("struct node { value: i32, next: *node } fn main() {}").is_empty());
+    }
+
+    #[test]
+    fn alignment_is_a_power_of_two_up_to_8() {
+        let source = "struct a align(8) { x: u8 } struct b align(16) { x: u8 } \
+                      struct c align(3) { x: u8 } fn main() {}";
+        assert_eq!(codes(source), [E_UNEXPECTED_TOKEN, E_UNEXPECTED_TOKEN]);
+    }
+
+    #[test]
+    fn duplicate_types() {
+        let source = "struct p { x: i32 } struct p { y: i32 } struct u8 { z: i32 } fn main() {}";
+        assert_eq!(codes(source), [E_DUPLICATE_DEFINITION, E_DUPLICATE_DEFINITION]);
+    }
+
+    #[test]
+    fn duplicate_constants() {
+        let source = "const N: u32 = 1; const N: u32 = 2; fn f(N: u8) {} \
+                      fn main() { let N = 3; buf[N] N; }";
+        assert_eq!(codes(source), [E_DUPLICATE_DEFINITION; 4]);
+    }
+
+    #[test]
+    fn recovers_after_invalid_constants() {
+        let source = "const S: str = 1; const B: u8 = 300; const C: u32 = 1 / 0; \
+                      const D: u32 = B; fn main() { $exit(x y); }";
+        assert_eq!(
+            codes(source),
+            [
+                E_UNEXPECTED_TOKEN,
+                E_INVALID_LITERAL,
+                E_NOT_CONSTANT,
+                E_NOT_CONSTANT,
+                E_UNEXPECTED_TOKEN
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_are_replaced_by_their_value() {
+        let program = parse(tokenize("const N: u8 = 3 * 4; fn main() { $exit(N); }").unwrap());
+        let output = format!("{:?}", program.unwrap());
+        assert!(output.contains("Constant(\"N\", 12, U8"), "{}", output);
+    }
+    #[test]
+    fn constants_are_defined_in_order() {
+        let source = "const A: u32 = B + 1; const B: u32 = 1; \
+                      fn main() { buf[B] b; $exit(C); } const C: u32 = B;";
+        let errors = parse(tokenize(source).unwrap()).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].message, "constant `B` is used before its definition");
+        assert_eq!(errors[1].message, "constant `C` is used before its definition");
     }
 }
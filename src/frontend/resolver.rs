@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+
+use crate::{
+    extra::diagnostic::{
+        Diagnostic, E_DUPLICATE_DEFINITION, E_IMMUTABLE_ASSIGNMENT, E_UNKNOWN_FUNCTION,
+        E_UNKNOWN_VARIABLE, W_SHADOWED_NAME,
+    },
+    frontend::{parser::AstNode, span::Span},
+};
+
+pub type SymbolId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Function(usize), // number of parameters
+    Parameter,
+    Variable,
+    Buffer,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub mutable: bool,
+    pub span: Span, // the declaring node
+}
+
+/// Output of the resolution pass: every declared symbol, and for each
+/// declaration and identifier use the symbol it refers to. Nodes are keyed
+/// by the start offset of their span, like the slots of a `FrameLayout`.
+#[derive(Debug, Default)]
+pub struct Resolution {
+    pub symbols: Vec<Symbol>,
+    pub warnings: Vec<Diagnostic>,
+    resolved: HashMap<usize, SymbolId>,
+}
+
+impl Resolution {
+    /// Symbol declared or used by the node starting at `span`.
+    pub fn id_at(&self, span: Span) -> Option<SymbolId> {
+        self.resolved.get(&span.start).copied()
+    }
+
+    pub fn symbol_at(&self, span: Span) -> Option<&Symbol> {
+        self.id_at(span).map(|id| &self.symbols[id])
+    }
+}
+
+/// Checks that every name refers to something declared before it and
+/// records what each one resolves to. All problems are collected, warnings
+/// included, so they can be reported in one go.
+pub fn resolve(program: &AstNode) -> Result<Resolution, Vec<Diagnostic>> {
+    let mut resolver = Resolver::default();
+    resolver.visit(program);
+
+    if resolver.errors.is_empty() {
+        Ok(resolver.resolution)
+    } else {
+        let mut diagnostics = resolver.errors;
+        diagnostics.append(&mut resolver.resolution.warnings);
+        diagnostics.sort_by_key(|diagnostic| diagnostic.primary.span.start);
+        Err(diagnostics)
+    }
+}
+
+#[derive(Default)]
+struct Resolver {
+    resolution: Resolution,
+    functions: HashMap<String, SymbolId>,
+    scopes: Vec<HashMap<String, SymbolId>>, // blocks of the current function, innermost last
+    ended: HashMap<String, SymbolId>, // declarations of blocks that already closed
+    in_function: bool,
+    errors: Vec<Diagnostic>,
+}
+
+impl Resolver {
+    fn visit(&mut self, node: &AstNode) {
+        match node {
+            AstNode::Program(statements, _) => {
+                // Functions may be called above their definition
+                for stmt in statements {
+                    if let AstNode::FunctionDefinition(name, params, _, _, span) = stmt {
+                        self.declare_function(name, params.len(), *span);
+                    }
+                }
+
+                self.scopes.push(HashMap::new());
+                self.visit_all(statements);
+                self.scopes.pop();
+            }
+
+            AstNode::FunctionDefinition(name, params, _, body, span) => {
+                // Nested definitions are rejected by the generator, but their
+                // bodies are still checked
+                if self.in_function {
+                    self.declare_function(name, params.len(), *span);
+                }
+
+                // Functions only see their own parameters and locals
+                let outer = std::mem::replace(&mut self.scopes, vec![HashMap::new()]);
+                let was_in_function = std::mem::replace(&mut self.in_function, true);
+                self.ended.clear();
+                for param in params {
+                    if let AstNode::Parameter(param_name, _, param_span) = param {
+                        self.declare(param_name, SymbolKind::Parameter, false, *param_span);
+                    }
+                }
+                self.visit_all(body);
+                self.scopes = outer;
+                self.in_function = was_in_function;
+            }
+
+            AstNode::VariableDeclaration(name, mutable, _, value, span) => {
+                // The value is resolved first, so `let x = x + 1;` reads the previous `x`
+                self.visit(value);
+                self.declare(name, SymbolKind::Variable, *mutable, *span);
+            }
+
+            AstNode::Identifier(name, _, _, span) => {
+                self.declare(name, SymbolKind::Buffer, false, *span);
+            }
+
+            AstNode::Assignment(name, value, span) => {
+                self.visit(value);
+                self.use_variable(name, *span);
+                self.check_assignable(name, *span);
+            }
+
+            AstNode::DerefAssignment(pointer, value, _) => {
+                self.visit(pointer);
+                self.visit(value);
+            }
+
+            AstNode::FieldAssignment(base, _, value, _) => {
+                self.visit(base);
+                self.visit(value);
+            }
+
+            AstNode::IndexAssignment(name, index, value, span) => {
+                self.use_variable(name, *span);
+                self.visit(index);
+                self.visit(value);
+            }
+
+            AstNode::If(condition, then_body, else_body, _) => {
+                self.visit(condition);
+                self.visit_block(then_body);
+                self.visit_block(else_body);
+            }
+
+            AstNode::While(condition, body, _) => {
+                self.visit(condition);
+                self.visit_block(body);
+            }
+
+            AstNode::Loop(body, _) => self.visit_block(body),
+
+            AstNode::Return(Some(value), _) => self.visit(value),
+
+            AstNode::Variable(name, span) => self.use_variable(name, *span),
+
+            AstNode::Index(name, index, span) => {
+                self.use_variable(name, *span);
+                self.visit(index);
+            }
+
+            AstNode::Slice(name, from, to, span) => {
+                self.use_variable(name, *span);
+                for bound in [from, to].into_iter().flatten() {
+                    self.visit(bound);
+                }
+            }
+
+            AstNode::Binary(_, lhs, rhs, _) => {
+                self.visit(lhs);
+                self.visit(rhs);
+            }
+
+            AstNode::Unary(_, operand, _) | AstNode::Field(operand, _, _) => self.visit(operand),
+
+            AstNode::Call(name, args, span) => {
+                self.use_function(name, *span);
+                self.visit_all(args);
+            }
+
+            AstNode::Syscall(_, args, _) => self.visit_all(args),
+
+            AstNode::RawSyscall(number, args, _) => {
+                self.visit(number);
+                self.visit_all(args);
+            }
+
+            AstNode::Number(_, _)
+            | AstNode::Constant(_, _, _, _)
+            | AstNode::ConstDefinition(_, _)
+            | AstNode::Bool(_, _)
+            | AstNode::String(_, _)
+            | AstNode::Parameter(_, _, _)
+            | AstNode::StructDefinition(_, _)
+            | AstNode::Break(_)
+            | AstNode::Continue(_)
+            | AstNode::Return(None, _) => {}
+        }
+    }
+
+    fn visit_all(&mut self, nodes: &[AstNode]) {
+        for node in nodes {
+            self.visit(node);
+        }
+    }
+
+    /// Statements inside `{ .. }`: whatever they declare ends with the block.
+    fn visit_block(&mut self, statements: &[AstNode]) {
+        self.scopes.push(HashMap::new());
+        self.visit_all(statements);
+        if let Some(scope) = self.scopes.pop() {
+            self.ended.extend(scope);
+        }
+    }
+
+    fn add_symbol(&mut self, kind: SymbolKind, mutable: bool, span: Span) -> SymbolId {
+        let id = self.resolution.symbols.len();
+        self.resolution.symbols.push(Symbol {
+            kind,
+            mutable,
+            span,
+        });
+        self.resolution.resolved.insert(span.start, id);
+        id
+    }
+
+    fn declare_function(&mut self, name: &str, arity: usize, span: Span) {
+        if let Some(&previous) = self.functions.get(name) {
+            self.errors.push(
+                Diagnostic::error(
+                    E_DUPLICATE_DEFINITION,
+                    format!("function `{}` is defined multiple times", name),
+                    span,
+                )
+                .with_label("redefined here")
+                .with_secondary(self.resolution.symbols[previous].span, "first defined here"),
+            );
+            return;
+        }
+
+        let id = self.add_symbol(SymbolKind::Function(arity), false, span);
+        self.functions.insert(name.to_string(), id);
+    }
+
+    /// Declares a parameter, variable or buffer in the innermost scope.
+    /// `let` may rebind a name of the same block, but parameters and buffers
+    /// can't be declared twice in one block, and hiding a name of an
+    /// enclosing block is worth a warning.
+    fn declare(&mut self, name: &str, kind: SymbolKind, mutable: bool, span: Span) {
+        let innermost = self.scopes.len() - 1;
+        let previous = self.scopes.iter().enumerate().rev().find_map(|(depth, scope)| {
+            scope.get(name).map(|&id| (depth, self.resolution.symbols[id].clone()))
+        });
+
+        match previous {
+            Some((depth, previous)) if depth == innermost => {
+                let rebinding = kind == SymbolKind::Variable
+                    && matches!(previous.kind, SymbolKind::Variable | SymbolKind::Parameter);
+                if !rebinding {
+                    self.errors.push(
+                        Diagnostic::error(
+                            E_DUPLICATE_DEFINITION,
+                            format!("`{}` is declared multiple times in the same block", name),
+                            span,
+                        )
+                        .with_label("declared again here")
+                        .with_secondary(previous.span, "first declared here"),
+                    );
+                    return;
+                }
+            }
+            Some((_, previous)) => {
+                let warning = Diagnostic::warning(
+                    W_SHADOWED_NAME,
+                    format!("`{}` shadows a declaration from an enclosing block", name),
+                    span,
+                )
+                .with_label(format!("this `{}` hides the outer one until the block ends", name))
+                .with_secondary(previous.span, "shadowed declaration");
+
+                let warning = if previous.mutable {
+                    warning.with_note(format!(
+                        "to update the outer variable, assign with `{} = ...` instead",
+                        name
+                    ))
+                } else {
+                    warning
+                };
+                self.resolution.warnings.push(warning);
+            }
+            None => {}
+        }
+
+        let id = self.add_symbol(kind, mutable, span);
+        self.scopes[innermost].insert(name.to_string(), id);
+    }
+
+    fn use_variable(&mut self, name: &str, span: Span) {
+        let found = self.scopes.iter().rev().find_map(|scope| scope.get(name)).copied();
+        if let Some(id) = found {
+            self.resolution.resolved.insert(span.start, id);
+            return;
+        }
+
+        let visible: Vec<&str> =
+            self.scopes.iter().flat_map(|scope| scope.keys()).map(String::as_str).collect();
+        let diagnostic = Diagnostic::error(
+            E_UNKNOWN_VARIABLE,
+            format!("cannot find value `{}` in this scope", name),
+            span,
+        )
+        .with_label("not found in this scope");
+
+        let diagnostic = match (self.ended.get(name), similar_name(name, visible)) {
+            (Some(&id), _) => {
+                let declared = self.resolution.symbols[id].span;
+                diagnostic
+                    .with_secondary(declared, "declared in a block that has ended")
+                    .with_note(format!("declare `{}` before the block to use it afterwards", name))
+            }
+            (None, Some(similar)) => {
+                diagnostic.with_note(format!("a value named `{}` exists", similar))
+            }
+            (None, None) => diagnostic,
+        };
+        self.errors.push(diagnostic);
+    }
+
+    /// Rejects `name = value;` unless `name` was declared with `let mut`.
+    fn check_assignable(&mut self, name: &str, span: Span) {
+        let Some(symbol) = self.resolution.symbol_at(span) else {
+            return;
+        };
+        if symbol.mutable {
+            return;
+        }
+
+        let what = match symbol.kind {
+            SymbolKind::Variable => "immutable variable",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Buffer => "buffer",
+            SymbolKind::Function(_) => "function",
+        };
+        let diagnostic = Diagnostic::error(
+            E_IMMUTABLE_ASSIGNMENT,
+            format!("cannot assign to {} `{}`", what, name),
+            span,
+        )
+        .with_label("cannot assign")
+        .with_secondary(symbol.span, "declared here");
+
+        let diagnostic = if symbol.kind == SymbolKind::Variable {
+            diagnostic.with_note(format!("declare it with `let mut {}` to make it mutable", name))
+        } else {
+            diagnostic
+        };
+        self.errors.push(diagnostic);
+    }
+
+    fn use_function(&mut self, name: &str, span: Span) {
+        if let Some(&id) = self.functions.get(name) {
+            self.resolution.resolved.insert(span.start, id);
+            return;
+        }
+
+        let defined: Vec<&str> = self.functions.keys().map(String::as_str).collect();
+        let diagnostic = Diagnostic::error(
+            E_UNKNOWN_FUNCTION,
+            format!("cannot find function `{}`", name),
+            span,
+        )
+        .with_label("not defined anywhere in this file");
+
+        self.errors.push(match similar_name(name, defined) {
+            Some(similar) => diagnostic.with_note(format!("a function named `{}` exists", similar)),
+            None => diagnostic,
+        });
+    }
+}
+
+/// Closest candidate within a few typos of `name`, for "did you mean" notes.
+fn similar_name<'a>(name: &str, candidates: Vec<&'a str>) -> Option<&'a str> {
+    let max_distance = name.len().div_ceil(3);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::{parser::parse, tokenizer::tokenize};
+
+    /// Codes of the errors and warnings reported for `source`, in order.
+    fn codes(source: &str) -> Vec<&'static str> {
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let diagnostics = match resolve(&program) {
+            Ok(resolution) => resolution.warnings,
+            Err(diagnostics) => diagnostics,
+        };
+        diagnostics.iter().map(|d| d.code).collect()
+    }
+
+    #[test]
+    fn duplicate_functions() {
+        let source = "fn twice() {} fn twice() {} fn main() {}";
+        assert_eq!(codes(source), [E_DUPLICATE_DEFINITION]);
+    }
+
+    #[test]
+    fn duplicates_in_one_block() {
+        let source = "fn f(a: 4, a: 4) {} fn main() { buf[4] b; buf[4] b; }";
+        assert_eq!(codes(source), [E_DUPLICATE_DEFINITION, E_DUPLICATE_DEFINITION]);
+    }
+
+    #[test]
+    fn let_rebinds_in_the_same_block() {
+        assert!(codes("fn main() { let x = 1; let x = x + 1; $exit(x); }").is_empty());
+    }
+
+    #[test]
+    fn shadowing_an_outer_block() {
+        let source = "fn main() { let x = 1; if x > 0 { let x = 2; $exit(x); } }";
+        assert_eq!(codes(source), [W_SHADOWED_NAME]);
+    }
+
+    #[test]
+    fn unknown_names() {
+        let source = "fn main() { y = 1; sub(1); if 1 { let z = 2; } $exit(z); }";
+        assert_eq!(codes(source), [E_UNKNOWN_VARIABLE, E_UNKNOWN_FUNCTION, E_UNKNOWN_VARIABLE]);
+    }
+
+    #[test]
+    fn only_mutable_variables_can_be_assigned() {
+        assert!(codes("fn main() { let mut x = 1; x = x + 1; $exit(x); }").is_empty());
+        let source = "fn f(a: i32) { a = 1; } fn main() { let x = 1; x = 2; buf[4] b; b = 1; }";
+        assert_eq!(codes(source), [E_IMMUTABLE_ASSIGNMENT; 3]);
+    }
+
+    #[test]
+    fn functions_can_be_called_before_their_definition() {
+        assert!(codes("fn main() { $exit(f()); } fn f() { return main(); }").is_empty());
+    }
+
+    #[test]
+    fn warnings_are_kept_alongside_errors() {
+        let source = "fn main() { let x = 1; while x > 0 { let x = 0; $exit(y); } }";
+        assert_eq!(codes(source), [W_SHADOWED_NAME, E_UNKNOWN_VARIABLE]);
+    }
+}
@@ -1,7 +1,16 @@
+use std::{fmt, iter::Peekable, str::CharIndices};
+
+use crate::{
+    extra::diagnostic::{
+        Diagnostic, E_INVALID_LITERAL, E_UNKNOWN_CHARACTER, E_UNTERMINATED_COMMENT,
+    },
+    frontend::span::Span,
+};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Number(i32),
-    String(String),
+    Number(i64), // anything up to `u32::MAX`, the checker decides what fits
+    String(Vec<u8>), // decoded bytes, escapes already resolved
 
     Function,
     Identifier(String),
@@ -15,92 +24,514 @@ pub enum Token {
     Colon,
     Equals,
     Let,
+    Mut,
     Buf,
+    Struct,
+    Const,
+    Sizeof,
+    Offsetof,
+    If,
+    Else,
+    While,
+    Loop,
+    Break,
+    Continue,
+    Return,
+    True,
+    False,
+    Arrow,
     BracketOpen,
     BracketClose,
+    Dot,
+    DotDot,
+
+    // operators
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    ShiftLeft,
+    ShiftRight,
+    DoubleEquals,
+    NotEquals,
+    LessThan,
+    LessEquals,
+    GreaterThan,
+    GreaterEquals,
+    DoubleAmpersand,
+    DoublePipe,
+    Bang,
+
+    DocComment(String), // `///` comment, kept for documentation tooling
+    Eof,                // End of File
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "`{}`", n),
+            Token::String(s) => write!(f, "`\"{}\"`", String::from_utf8_lossy(s).escape_debug()),
+            Token::Function => write!(f, "`fn`"),
+            Token::Identifier(id) => write!(f, "`{}`", id),
+            Token::Syscall(name) => write!(f, "`${}`", name),
+            Token::ParentOpen => write!(f, "`(`"),
+            Token::ParentClose => write!(f, "`)`"),
+            Token::CurlyOpen => write!(f, "`{{`"),
+            Token::CurlyClose => write!(f, "`}}`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Semicolon => write!(f, "`;`"),
+            Token::Colon => write!(f, "`:`"),
+            Token::Equals => write!(f, "`=`"),
+            Token::Let => write!(f, "`let`"),
+            Token::Mut => write!(f, "`mut`"),
+            Token::Buf => write!(f, "`buf`"),
+            Token::Struct => write!(f, "`struct`"),
+            Token::Const => write!(f, "`const`"),
+            Token::Sizeof => write!(f, "`sizeof`"),
+            Token::Offsetof => write!(f, "`offsetof`"),
+            Token::If => write!(f, "`if`"),
+            Token::Else => write!(f, "`else`"),
+            Token::While => write!(f, "`while`"),
+            Token::Loop => write!(f, "`loop`"),
+            Token::Break => write!(f, "`break`"),
+            Token::Continue => write!(f, "`continue`"),
+            Token::Return => write!(f, "`return`"),
+            Token::True => write!(f, "`true`"),
+            Token::False => write!(f, "`false`"),
+            Token::Arrow => write!(f, "`->`"),
+            Token::BracketOpen => write!(f, "`[`"),
+            Token::BracketClose => write!(f, "`]`"),
+            Token::Dot => write!(f, "`.`"),
+            Token::DotDot => write!(f, "`..`"),
+            Token::Plus => write!(f, "`+`"),
+            Token::Minus => write!(f, "`-`"),
+            Token::Star => write!(f, "`*`"),
+            Token::Slash => write!(f, "`/`"),
+            Token::Percent => write!(f, "`%`"),
+            Token::Ampersand => write!(f, "`&`"),
+            Token::Pipe => write!(f, "`|`"),
+            Token::Caret => write!(f, "`^`"),
+            Token::ShiftLeft => write!(f, "`<<`"),
+            Token::ShiftRight => write!(f, "`>>`"),
+            Token::DoubleEquals => write!(f, "`==`"),
+            Token::NotEquals => write!(f, "`!=`"),
+            Token::LessThan => write!(f, "`<`"),
+            Token::LessEquals => write!(f, "`<=`"),
+            Token::GreaterThan => write!(f, "`>`"),
+            Token::GreaterEquals => write!(f, "`>=`"),
+            Token::DoubleAmpersand => write!(f, "`&&`"),
+            Token::DoublePipe => write!(f, "`||`"),
+            Token::Bang => write!(f, "`!`"),
+            Token::DocComment(_) => write!(f, "doc comment"),
+            Token::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
 
-    EOF, // End of File
-    Unknown,
+struct Lexer<'a> {
+    script: &'a str,
+    iter: Peekable<CharIndices<'a>>,
+    line: usize,
+    column: usize,
 }
 
-pub fn tokenize(script: &str) -> Vec<Token> {
+impl<'a> Lexer<'a> {
+    fn new(script: &'a str) -> Self {
+        Self {
+            script,
+            iter: script.char_indices().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.iter.peek().map(|&(_, ch)| ch)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.iter.peek().map_or(self.script.len(), |&(i, _)| i)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let (_, ch) = self.iter.next()?;
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
+
+    /// Two-character operators: if the next char is `second`, consume it and
+    /// return `double`, otherwise `single`.
+    fn either(&mut self, second: char, double: Token, single: Token) -> Token {
+        if self.peek() == Some(second) {
+            self.next();
+            double
+        } else {
+            single
+        }
+    }
+
+    /// Skips the rest of a `/* */` comment whose opening `/*` was already
+    /// consumed. Comments nest, so every `/*` needs its own `*/`. Returns
+    /// false if the input ends first.
+    fn skip_block_comment(&mut self) -> bool {
+        let mut depth = 1;
+        while let Some(ch) = self.next() {
+            match (ch, self.peek()) {
+                ('/', Some('*')) => {
+                    self.next();
+                    depth += 1;
+                }
+                ('*', Some('/')) => {
+                    self.next();
+                    depth -= 1;
+                    if depth == 0 {
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Decodes the escape sequence following a `\\` inside a string literal
+    /// and appends the resulting bytes to `out`.
+    fn read_escape(&mut self, out: &mut Vec<u8>) -> Result<(), String> {
+        let byte = match self.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => b'\0',
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('x') => {
+                let mut digits = String::new();
+                for _ in 0..2 {
+                    match self.peek() {
+                        Some(c) if c.is_ascii_hexdigit() => digits.push(self.next().unwrap()),
+                        _ => return Err("`\\x` must be followed by two hex digits".to_string()),
+                    }
+                }
+                u8::from_str_radix(&digits, 16).unwrap()
+            }
+            Some('u') => {
+                if self.peek() != Some('{') {
+                    return Err("`\\u` must be followed by `{`".to_string());
+                }
+                self.next();
+
+                let mut digits = String::new();
+                self.take_while(|c| c.is_ascii_hexdigit(), &mut digits);
+                if self.peek() != Some('}') {
+                    return Err("unterminated unicode escape".to_string());
+                }
+                self.next();
+
+                let ch = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .filter(|_| (1..=6).contains(&digits.len()))
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("`{}` is not a valid unicode scalar value", digits))?;
+                let mut utf8 = [0; 4];
+                out.extend_from_slice(ch.encode_utf8(&mut utf8).as_bytes());
+                return Ok(());
+            }
+            Some(other) => return Err(format!("unknown escape sequence `\\{}`", other)),
+            None => return Err("unterminated escape sequence".to_string()),
+        };
+
+        out.push(byte);
+        Ok(())
+    }
+
+    fn take_while(&mut self, mut pred: impl FnMut(char) -> bool, out: &mut String) {
+        while let Some(next_ch) = self.peek() {
+            if pred(next_ch) {
+                out.push(next_ch);
+                self.next();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+pub fn tokenize(script: &str) -> Result<Vec<SpannedToken>, Vec<Diagnostic>> {
     let mut tokens = Vec::new();
-    let mut iter = script.chars().peekable();
-
-    while let Some(ch) = iter.next() {
-        match ch {
-            ' ' | '\n' | '\t' => continue, // Skip whitespace
-
-            '(' => tokens.push(Token::ParentOpen),
-            ')' => tokens.push(Token::ParentClose),
-            '{' => tokens.push(Token::CurlyOpen),
-            '}' => tokens.push(Token::CurlyClose),
-            ',' => tokens.push(Token::Comma),
-            ';' => tokens.push(Token::Semicolon),
-            ':' => tokens.push(Token::Colon),
-            '=' => tokens.push(Token::Equals),
-            '[' => tokens.push(Token::BracketOpen),
-            ']' => tokens.push(Token::BracketClose),
+    let mut errors = Vec::new();
+    let mut lexer = Lexer::new(script);
+
+    loop {
+        let start = lexer.offset();
+        let (line, column) = (lexer.line, lexer.column);
+        let Some(ch) = lexer.next() else {
+            break;
+        };
+
+        let token = match ch {
+            ' ' | '\n' | '\t' | '\r' => continue, // Skip whitespace
+
+            '(' => Token::ParentOpen,
+            ')' => Token::ParentClose,
+            '{' => Token::CurlyOpen,
+            '}' => Token::CurlyClose,
+            ',' => Token::Comma,
+            ';' => Token::Semicolon,
+            ':' => Token::Colon,
+            '[' => Token::BracketOpen,
+            ']' => Token::BracketClose,
+            '.' => lexer.either('.', Token::DotDot, Token::Dot),
+            '+' => Token::Plus,
+            '-' => lexer.either('>', Token::Arrow, Token::Minus),
+            '*' => Token::Star,
+            '%' => Token::Percent,
+            '^' => Token::Caret,
+            '=' => lexer.either('=', Token::DoubleEquals, Token::Equals),
+            '!' => lexer.either('=', Token::NotEquals, Token::Bang),
+            '&' => lexer.either('&', Token::DoubleAmpersand, Token::Ampersand),
+            '|' => lexer.either('|', Token::DoublePipe, Token::Pipe),
+            '<' => match lexer.peek() {
+                Some('<') => lexer.either('<', Token::ShiftLeft, Token::LessThan),
+                _ => lexer.either('=', Token::LessEquals, Token::LessThan),
+            },
+            '>' => match lexer.peek() {
+                Some('>') => lexer.either('>', Token::ShiftRight, Token::GreaterThan),
+                _ => lexer.either('=', Token::GreaterEquals, Token::GreaterThan),
+            },
+
+            '/' if lexer.peek() == Some('/') => {
+                lexer.next();
+                // Exactly three slashes; `////` and longer are plain comments
+                let mut is_doc = lexer.peek() == Some('/');
+                if is_doc {
+                    lexer.next();
+                    is_doc = lexer.peek() != Some('/');
+                }
+
+                let mut comment = String::new();
+                lexer.take_while(|c| c != '\n', &mut comment);
+                if !is_doc {
+                    continue;
+                }
+                Token::DocComment(comment.trim().to_string())
+            }
+
+            '/' if lexer.peek() == Some('*') => {
+                lexer.next();
+                if !lexer.skip_block_comment() {
+                    let span = Span::new(start, start + 2, line, column);
+                    errors.push(
+                        Diagnostic::error(E_UNTERMINATED_COMMENT, "unterminated block comment", span)
+                            .with_label("comment starts here"),
+                    );
+                }
+                continue;
+            }
+
+            '/' => Token::Slash,
 
             '$' => {
                 let mut syscall = String::new();
-                while let Some(&next_ch) = iter.peek() {
-                    if next_ch.is_alphanumeric() || next_ch == '_' {
-                        syscall.push(iter.next().unwrap());
-                    } else {
-                        break;
-                    }
-                }
-                tokens.push(Token::Syscall(syscall));
+                lexer.take_while(|c| c.is_alphanumeric() || c == '_', &mut syscall);
+                Token::Syscall(syscall)
             }
 
             '"' => {
-                let mut string = String::new();
-                while let Some(&next_ch) = iter.peek() {
-                    if next_ch == '"' {
-                        iter.next(); // Consume the closing quote
-                        break;
-                    } else {
-                        string.push(iter.next().unwrap());
+                let mut string = Vec::new();
+                loop {
+                    let escape_start = lexer.offset();
+                    let (escape_line, escape_column) = (lexer.line, lexer.column);
+                    match lexer.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            if let Err(message) = lexer.read_escape(&mut string) {
+                                let span =
+                                    Span::new(escape_start, lexer.offset(), escape_line, escape_column);
+                                errors.push(
+                                    Diagnostic::error(E_INVALID_LITERAL, message, span)
+                                        .with_label("invalid escape")
+                                        .with_note(
+                                            "valid escapes are \\n \\t \\r \\\\ \\\" \\0 \\xNN and \\u{...}",
+                                        ),
+                                );
+                            }
+                        }
+                        Some(ch) => {
+                            let mut utf8 = [0; 4];
+                            string.extend_from_slice(ch.encode_utf8(&mut utf8).as_bytes());
+                        }
+                        None => {
+                            let span = Span::new(start, lexer.offset(), line, column);
+                            errors.push(
+                                Diagnostic::error(E_INVALID_LITERAL, "unterminated string literal", span)
+                                    .with_label("string starts here"),
+                            );
+                            break;
+                        }
                     }
                 }
-                tokens.push(Token::String(string));
+                Token::String(string)
             }
 
             c if c.is_alphabetic() || c == '_' => {
                 let mut identifier = c.to_string();
-                while let Some(&next_ch) = iter.peek() {
-                    if next_ch.is_alphanumeric() || next_ch == '_' {
-                        identifier.push(iter.next().unwrap());
-                    } else {
-                        break;
-                    }
-                }
+                lexer.take_while(|c| c.is_alphanumeric() || c == '_', &mut identifier);
 
                 match identifier.as_str() {
-                    "fn" => tokens.push(Token::Function),
-                    "let" => tokens.push(Token::Let),
-                    "buf" => tokens.push(Token::Buf),
-                    _ => tokens.push(Token::Identifier(identifier)),
+                    "fn" => Token::Function,
+                    "let" => Token::Let,
+                    "mut" => Token::Mut,
+                    "buf" => Token::Buf,
+                    "struct" => Token::Struct,
+                    "const" => Token::Const,
+                    "sizeof" => Token::Sizeof,
+                    "offsetof" => Token::Offsetof,
+                    "if" => Token::If,
+                    "else" => Token::Else,
+                    "while" => Token::While,
+                    "loop" => Token::Loop,
+                    "break" => Token::Break,
+                    "continue" => Token::Continue,
+                    "return" => Token::Return,
+                    "true" => Token::True,
+                    "false" => Token::False,
+                    _ => Token::Identifier(identifier),
                 }
             }
 
-            c if c.is_digit(10) => {
+            c if c.is_ascii_digit() => {
                 let mut number = c.to_string();
-                while let Some(&next_ch) = iter.peek() {
-                    if next_ch.is_digit(10) {
-                        number.push(iter.next().unwrap());
-                    } else {
-                        break;
+                lexer.take_while(|c| c.is_ascii_digit(), &mut number);
+                match number.parse::<i64>() {
+                    Ok(n) if n <= u32::MAX as i64 => Token::Number(n),
+                    _ => {
+                        let span = Span::new(start, lexer.offset(), line, column);
+                        errors.push(
+                            Diagnostic::error(E_INVALID_LITERAL, "number literal is too large", span)
+                                .with_label("does not fit in 32 bits"),
+                        );
+                        continue;
                     }
                 }
-                tokens.push(Token::Number(number.parse().unwrap()));
             }
 
-            _ => tokens.push(Token::Unknown),
-        }
+            other => {
+                let span = Span::new(start, lexer.offset(), line, column);
+                errors.push(
+                    Diagnostic::error(E_UNKNOWN_CHARACTER, format!("unknown character `{}`", other), span)
+                        .with_label("not valid here"),
+                );
+                continue;
+            }
+        };
+
+        let span = Span::new(start, lexer.offset(), line, column);
+        tokens.push(SpannedToken { token, span });
+    }
+
+    let end = script.len();
+    tokens.push(SpannedToken {
+        token: Token::Eof,
+        span: Span::new(end, end, lexer.line, lexer.column),
+    });
+
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    tokens.push(Token::EOF);
-    tokens
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source).unwrap().into_iter().map(|t| t.token).collect()
+    }
+
+    #[test]
+    fn nested_block_comments() {
+        let source = "let /* a /* b */ still comment */ x /**/ /* /* */ */";
+        assert_eq!(tokens(source), [Token::Let, Token::Identifier("x".into()), Token::Eof]);
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let errors = tokenize("let x; /* a /* b */ c").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, E_UNTERMINATED_COMMENT);
+        assert_eq!(errors[0].primary.span.start, 7);
+    }
+
+    #[test]
+    fn line_and_doc_comments() {
+        let source = "// note\n/// Docs here \nlet // trailing\nx";
+        assert_eq!(
+            tokens(source),
+            [
+                Token::DocComment("Docs here".into()),
+                Token::Let,
+                Token::Identifier("x".into()),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn only_three_slashes_make_a_doc_comment() {
+        let source = "//// banner\n//////////\n///x\nlet";
+        assert_eq!(tokens(source), [Token::DocComment("x".into()), Token::Let, Token::Eof]);
+    }
+
+    #[test]
+    fn string_escapes() {
+        let source = r#""a\"b\\ \n\t\r\0 \x41\x7f \u{e9}\u{1F431} ♥""#;
+        let expected = "a\"b\\ \n\t\r\0 A\x7f é🐱 ♥".as_bytes().to_vec();
+        assert_eq!(tokens(source), [Token::String(expected), Token::Eof]);
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        let source = r#""\q \x4 \u41 \u{110000} \u{}""#;
+        let errors = tokenize(source).unwrap_err();
+        let messages: Vec<_> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "unknown escape sequence `\\q`",
+                "`\\x` must be followed by two hex digits",
+                "`\\u` must be followed by `{`",
+                "`110000` is not a valid unicode scalar value",
+                "`` is not a valid unicode scalar value",
+            ]
+        );
+    }
+
+    #[test]
+    fn operators_take_the_longest_match() {
+        use Token::*;
+        assert_eq!(
+            tokens("<<= < <= >> >= == = != ! && & || |"),
+            [
+                ShiftLeft, Equals, LessThan, LessEquals, ShiftRight, GreaterEquals, DoubleEquals,
+                Equals, NotEquals, Bang, DoubleAmpersand, Ampersand, DoublePipe, Pipe, Eof
+            ]
+        );
+    }
 }
@@ -0,0 +1,285 @@
+use std::{cell::OnceCell, fmt, rc::Rc};
+
+/// Static type of a value, variable, parameter or function result.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    Bool,
+    Ptr(Box<Type>),
+    Array(Box<Type>, i32), // element type, length
+    Str,                   // string literal in read-only data, its length is known
+    Struct(Rc<StructDef>),
+}
+
+/// Field of a struct and where it starts.
+#[derive(Debug, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub ty: Type,
+    pub offset: i32,
+}
+
+/// A `struct` declaration. Its layout is filled in once the whole body has
+/// been parsed, so that fields can point to the struct being declared.
+pub struct StructDef {
+    pub name: String,
+    layout: OnceCell<StructLayout>,
+}
+
+/// The same layout a C compiler would pick: every field is aligned to its
+/// own alignment and the size is padded to a multiple of the struct's
+/// alignment.
+struct StructLayout {
+    fields: Vec<Field>,
+    size: i32,
+    align: i32,
+}
+
+impl StructDef {
+    /// A struct whose fields are still unknown.
+    pub fn declare(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            layout: OnceCell::new(),
+        }
+    }
+
+    /// Lays out `fields` in declaration order. `packed` drops all padding
+    /// and `align` raises the alignment of the struct as a whole.
+    pub fn lay_out(&self, fields: Vec<(String, Type)>, packed: bool, align: Option<i32>) {
+        let mut offset = 0;
+        let mut struct_align = 1;
+        let mut laid_out = Vec::new();
+
+        for (field_name, ty) in fields {
+            let field_align = if packed { 1 } else { ty.align() };
+            offset = align_to(offset, field_align);
+            struct_align = struct_align.max(field_align);
+
+            let size = ty.size();
+            laid_out.push(Field {
+                name: field_name,
+                ty,
+                offset,
+            });
+            offset += size;
+        }
+
+        let struct_align = struct_align.max(align.unwrap_or(1));
+        let _ = self.layout.set(StructLayout {
+            fields: laid_out,
+            size: align_to(offset, struct_align),
+            align: struct_align,
+        });
+    }
+
+    /// Whether the body has been laid out, i.e. the struct can be used by
+    /// value and not just behind a pointer.
+    pub fn is_complete(&self) -> bool {
+        self.layout.get().is_some()
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        self.layout.get().map_or(&[], |layout| &layout.fields)
+    }
+
+    pub fn size(&self) -> i32 {
+        self.layout.get().map_or(0, |layout| layout.size)
+    }
+
+    pub fn align(&self) -> i32 {
+        self.layout.get().map_or(1, |layout| layout.align)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields().iter().find(|field| field.name == name)
+    }
+}
+
+// Struct names are unique, and comparing by name keeps a struct that
+// points to itself from sending these into an endless loop
+impl PartialEq for StructDef {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for StructDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StructDef")
+            .field("name", &self.name)
+            .field("size", &self.size())
+            .field("align", &self.align())
+            .finish()
+    }
+}
+
+/// Largest alignment a struct may ask for: stack frames, and with them all
+/// buffers, are only 8-byte aligned.
+pub const MAX_ALIGN: i32 = 8;
+
+/// Rounds `offset` up to a multiple of `align`, which is a power of two.
+pub fn align_to(offset: i32, align: i32) -> i32 {
+    (offset + align - 1) & !(align - 1)
+}
+
+impl Type {
+    /// Built-in type called `name`, e.g. `u8` or `str`.
+    pub fn named(name: &str) -> Option<Type> {
+        match name {
+            "i8" => Some(Type::I8),
+            "u8" => Some(Type::U8),
+            "i16" => Some(Type::I16),
+            "u16" => Some(Type::U16),
+            "i32" => Some(Type::I32),
+            "u32" => Some(Type::U32),
+            "bool" => Some(Type::Bool),
+            "str" => Some(Type::Str),
+            _ => None,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(
+            self,
+            Type::I8 | Type::U8 | Type::I16 | Type::U16 | Type::I32 | Type::U32
+        )
+    }
+
+    /// Size in bytes.
+    pub fn size(&self) -> i32 {
+        match self {
+            Type::I8 | Type::U8 | Type::Bool => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::Ptr(_) | Type::Str => 4,
+            Type::Array(element, len) => element.size() * len,
+            Type::Struct(def) => def.size(),
+        }
+    }
+
+    /// Alignment in bytes.
+    pub fn align(&self) -> i32 {
+        match self {
+            Type::Array(element, _) => element.align(),
+            Type::Struct(def) => def.align(),
+            other => other.size(),
+        }
+    }
+
+    /// Arrays and structs only exist in memory; using one as a value gives
+    /// its address.
+    pub fn is_aggregate(&self) -> bool {
+        matches!(self, Type::Array(_, _) | Type::Struct(_))
+    }
+
+    /// Whether an integer literal `value` can be stored in this type.
+    pub fn fits(&self, value: i64) -> bool {
+        let (min, max) = match self {
+            Type::I8 => (i8::MIN as i64, i8::MAX as i64),
+            Type::U8 => (0, u8::MAX as i64),
+            Type::I16 => (i16::MIN as i64, i16::MAX as i64),
+            Type::U16 => (0, u16::MAX as i64),
+            Type::I32 => (i32::MIN as i64, i32::MAX as i64),
+            Type::U32 => (0, u32::MAX as i64),
+            _ => return false,
+        };
+        (min..=max).contains(&value)
+    }
+
+    /// What a value of this type turns into when it's copied into a
+    /// variable: arrays and strings decay to a pointer to their first
+    /// element, structs to a pointer to themselves.
+    pub fn decay(&self) -> Type {
+        match self {
+            Type::Array(element, _) => Type::Ptr(element.clone()),
+            Type::Str => Type::Ptr(Box::new(Type::U8)),
+            Type::Struct(_) => Type::Ptr(Box::new(self.clone())),
+            other => other.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::I8 => write!(f, "i8"),
+            Type::U8 => write!(f, "u8"),
+            Type::I16 => write!(f, "i16"),
+            Type::U16 => write!(f, "u16"),
+            Type::I32 => write!(f, "i32"),
+            Type::U32 => write!(f, "u32"),
+            Type::Bool => write!(f, "bool"),
+            Type::Ptr(target) => write!(f, "*{}", target),
+            Type::Array(element, len) => write!(f, "[{}; {}]", element, len),
+            Type::Str => write!(f, "str"),
+            Type::Struct(def) => write!(f, "{}", def.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn laid_out(fields: &[(&str, Type)], packed: bool, align: Option<i32>) -> StructDef {
+        let def = StructDef::declare("s");
+        let fields = fields.iter().map(|(name, ty)| (name.to_string(), ty.clone())).collect();
+        def.lay_out(fields, packed, align);
+        def
+    }
+
+    fn offsets(def: &StructDef) -> Vec<i32> {
+        def.fields().iter().map(|field| field.offset).collect()
+    }
+
+    #[test]
+    fn c_layout_pads_fields_and_size() {
+        let def = laid_out(&[("a", Type::U8), ("b", Type::U32), ("c", Type::U16)], false, None);
+        assert_eq!(offsets(&def), [0, 4, 8]);
+        assert_eq!((def.size(), def.align()), (12, 4));
+    }
+
+    #[test]
+    fn packed_drops_padding() {
+        let def = laid_out(&[("a", Type::U8), ("b", Type::U32), ("c", Type::U16)], true, None);
+        assert_eq!(offsets(&def), [0, 1, 5]);
+        assert_eq!((def.size(), def.align()), (7, 1));
+    }
+
+    #[test]
+    fn align_raises_the_struct_alignment() {
+        let def = laid_out(&[("a", Type::U8), ("b", Type::U16)], false, Some(8));
+        assert_eq!(offsets(&def), [0, 2]);
+        assert_eq!((def.size(), def.align()), (8, 8));
+    }
+
+    #[test]
+    fn nested_structs_and_arrays() {
+        let inner = Rc::new(laid_out(&[("x", Type::I16), ("y", Type::I16)], false, None));
+        let def = laid_out(
+            &[
+                ("tag", Type::U8),
+                ("point", Type::Struct(inner)),
+                ("data", Type::Array(Box::new(Type::U8), 3)),
+            ],
+            false,
+            None,
+        );
+        assert_eq!(offsets(&def), [0, 2, 6]);
+        assert_eq!((def.size(), def.align()), (10, 2));
+    }
+
+    #[test]
+    fn a_declared_struct_is_incomplete_until_laid_out() {
+        let def = StructDef::declare("node");
+        assert!(!def.is_complete());
+        def.lay_out(vec![("next".to_string(), Type::Ptr(Box::new(Type::U8)))], false, None);
+        assert!(def.is_complete());
+        assert_eq!(def.field("next").map(|field| field.offset), Some(0));
+    }
+}
@@ -3,8 +3,11 @@ use std::path::Path;
 use crate::{
     backend::arm32::{self, syscall_mapper::Architecture},
     extra::config::load_config,
+    extra::diagnostic::{Diagnostic, Label, Severity},
     backend::generator::generate,
+    frontend::checker::check,
     frontend::parser::parse,
+    frontend::resolver::resolve,
     frontend::tokenizer::tokenize,
 };
 
@@ -17,7 +20,7 @@ fn main() {
     let args: Vec<String> = std::env::args().collect();
 
     if args.len() < 2 {
-        eprintln!("Usage: {} <file_path>", args[0]);
+        eprintln!("Usage: {} <file_path> [--verbose] [--bounds-checks]", args[0]);
         std::process::exit(1);
     }
 
@@ -38,7 +41,9 @@ fn main() {
         .output
         .unwrap_or_else(|| format!("build/{}.s", file_stem));
 
-    let verbose = args.get(2).map_or(false, |arg| arg == "--verbose");
+    let flags = &args[2..];
+    let verbose = flags.iter().any(|arg| arg == "--verbose");
+    let bounds_checks = flags.iter().any(|arg| arg == "--bounds-checks");
 
     let script = match std::fs::read_to_string(file_path) {
         Ok(content) => content,
@@ -48,14 +53,26 @@ fn main() {
         }
     };
 
-    let tokens = tokenize(&script);
-    let ast_nodes = parse(tokens.clone());
+    let tokens = tokenize(&script)
+        .unwrap_or_else(|diagnostics| report_and_exit(&diagnostics, file_path, &script));
+    let ast_nodes = parse(tokens.clone())
+        .unwrap_or_else(|diagnostics| report_and_exit(&diagnostics, file_path, &script));
     if verbose {
         println!("AST Nodes: {:?}", ast_nodes);
         println!("Tokens: {:?}", tokens);
     }
 
-    let generator = generate(&ast_nodes, arch);
+    let resolution = resolve(&ast_nodes)
+        .unwrap_or_else(|diagnostics| report_and_exit(&diagnostics, file_path, &script));
+    for warning in &resolution.warnings {
+        eprintln!("{}", render_diagnostic(warning, file_path, &script));
+    }
+
+    let types = check(&ast_nodes, &resolution)
+        .unwrap_or_else(|diagnostics| report_and_exit(&diagnostics, file_path, &script));
+
+    let generator = generate(&ast_nodes, resolution, types, arch, bounds_checks)
+        .unwrap_or_else(|diagnostic| report_and_exit(&[*diagnostic], file_path, &script));
 
     let assembly_code = arm32::asm::generate_assembly(
         generator.section_writer.rodata,
@@ -68,15 +85,15 @@ fn main() {
 
     let output_path = Path::new(&output_path);
 
-    if let Some(parent_dir) = output_path.parent() {
-        if let Err(e) = std::fs::create_dir_all(parent_dir) {
-            eprintln!(
-                "Error creating output directory {}: {}",
-                parent_dir.display(),
-                e
-            );
-            std::process::exit(1);
-        }
+    if let Some(parent_dir) = output_path.parent()
+        && let Err(e) = std::fs::create_dir_all(parent_dir)
+    {
+        eprintln!(
+            "Error creating output directory {}: {}",
+            parent_dir.display(),
+            e
+        );
+        std::process::exit(1);
     }
 
     match std::fs::write(output_path, assembly_code) {
@@ -87,3 +104,215 @@ fn main() {
         }
     }
 }
+
+// ========== DIAGNOSTIC RENDERING ==========
+
+fn report_and_exit(diagnostics: &[Diagnostic], file_path: &str, source: &str) -> ! {
+    for diagnostic in diagnostics {
+        eprintln!("{}", render_diagnostic(diagnostic, file_path, source));
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    eprintln!(
+        "error: could not compile `{}` due to {} previous error{}",
+        file_path,
+        errors,
+        if errors == 1 { "" } else { "s" }
+    );
+    std::process::exit(1);
+}
+
+fn render_diagnostic(diagnostic: &Diagnostic, file_path: &str, source: &str) -> String {
+    let primary = &diagnostic.primary;
+    let gutter_width = std::iter::once(primary)
+        .chain(diagnostic.secondary.iter())
+        .map(|label| label.span.line.to_string().len())
+        .max()
+        .unwrap_or(1);
+    let gutter = " ".repeat(gutter_width);
+
+    let mut out = format!(
+        "{}[{}]: {}\n{}--> {}:{}:{}\n{} |\n",
+        diagnostic.severity.as_str(),
+        diagnostic.code,
+        diagnostic.message,
+        gutter,
+        file_path,
+        primary.span.line,
+        primary.span.column,
+        gutter
+    );
+
+    let mut labels: Vec<(&Label, char)> = std::iter::once((primary, '^'))
+        .chain(diagnostic.secondary.iter().map(|label| (label, '-')))
+        .collect();
+    labels.sort_by_key(|(label, _)| label.span.start);
+    for line in labels.chunk_by(|(a, _), (b, _)| a.span.line == b.span.line) {
+        out += &render_line(line, source, gutter_width);
+    }
+
+    if !diagnostic.notes.is_empty() {
+        out += &format!("{} |\n", gutter);
+    }
+    for note in &diagnostic.notes {
+        out += &format!("{} = note: {}\n", gutter, note);
+    }
+
+    out
+}
+
+/// Renders one source line with the markers of every label on it. As in
+/// rustc, the rightmost label's message follows the markers and the others
+/// hang below, connected to their markers by `|`.
+fn render_line(labels: &[(&Label, char)], source: &str, gutter_width: usize) -> String {
+    let first = labels[0].0;
+    let start = first.span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+
+    let expand = |text: &str| text.replace('\t', "    ");
+    let column = |offset: usize| expand(&source[line_start..offset]).chars().count();
+    let marks: Vec<(usize, usize, char, &str)> = labels
+        .iter()
+        .map(|(label, marker)| {
+            let start = label.span.start.clamp(line_start, line_end);
+            let end = label.span.end.clamp(start, line_end);
+            let width = (column(end) - column(start)).max(1);
+            (column(start), width, *marker, label.message.as_str())
+        })
+        .collect();
+
+    let end = marks.iter().map(|&(col, width, _, _)| col + width).max().unwrap();
+    let mut underline = vec![' '; end];
+    // Primary markers go last so they win where labels overlap
+    let mut layers = marks.clone();
+    layers.sort_by_key(|mark| mark.2 == '^');
+    for (col, width, marker, _) in layers {
+        underline[col..col + width].fill(marker);
+    }
+
+    let gutter = " ".repeat(gutter_width);
+    let (last, rest) = marks.split_last().unwrap();
+    let mut out = format!(
+        "{:>width$} | {}\n{} | {} {}\n",
+        first.span.line,
+        expand(&source[line_start..line_end]).trim_end(),
+        gutter,
+        underline.into_iter().collect::<String>(),
+        last.3,
+        width = gutter_width
+    );
+
+    let hanging: Vec<_> = rest.iter().filter(|mark| !mark.3.is_empty()).collect();
+    if let Some(rightmost) = hanging.last() {
+        let columns = hanging.iter().map(|mark| mark.0);
+        out += &format!("{} | {}\n", gutter, connectors(columns, rightmost.0 + 1));
+    }
+    for (i, mark) in hanging.iter().enumerate().rev() {
+        let columns = hanging[..i].iter().map(|mark| mark.0);
+        out += &format!("{} | {}{}\n", gutter, connectors(columns, mark.0), mark.3);
+    }
+
+    out
+}
+
+/// `width` spaces with a `|` at each of `columns`.
+fn connectors(columns: impl Iterator<Item = usize>, width: usize) -> String {
+    let mut row = vec![' '; width];
+    for column in columns {
+        if let Some(cell) = row.get_mut(column) {
+            *cell = '|';
+        }
+    }
+    row.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::span::Span;
+
+    /// Span of the first occurrence of `text` in `source`.
+    fn span_of(source: &str, text: &str) -> Span {
+        let start = source.find(text).unwrap();
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line = source[..start].matches('\n').count() + 1;
+        Span::new(start, start + text.len(), line, start - line_start + 1)
+    }
+
+    #[test]
+    fn renders_primary_label_and_notes() {
+        let source = "fn main() {\n    $exit(code);\n}\n";
+        let diagnostic = Diagnostic::error("E0005", "unknown variable", span_of(source, "code"))
+            .with_label("not found")
+            .with_note("declare it with `let`");
+
+        assert_eq!(
+            render_diagnostic(&diagnostic, "main.fy", source),
+            "error[E0005]: unknown variable\n \
+             --> main.fy:2:11\n  \
+             |\n\
+             2 |     $exit(code);\n  \
+             |           ^^^^ not found\n  \
+             |\n  \
+             = note: declare it with `let`\n"
+        );
+    }
+
+    #[test]
+    fn gutter_fits_the_widest_line_number() {
+        let source = format!("let a = 1;{}let a = 2;\n", "\n".repeat(9));
+        let diagnostic = Diagnostic::error("E0005", "duplicate", span_of(&source, "a = 2"))
+            .with_label("redefined here")
+            .with_secondary(span_of(&source, "a = 1"), "first defined here");
+
+        assert_eq!(
+            render_diagnostic(&diagnostic, "main.fy", &source),
+            "error[E0005]: duplicate\n  \
+             --> main.fy:10:5\n   \
+             |\n \
+             1 | let a = 1;\n   \
+             |     ----- first defined here\n\
+             10 | let a = 2;\n   \
+             |     ^^^^^ redefined here\n"
+        );
+    }
+
+    #[test]
+    fn tabs_are_expanded_under_the_carets() {
+        let source = "\tlet\ts = 1;\n";
+        let diagnostic = Diagnostic::error("E0003", "oops", span_of(source, "s"));
+
+        assert_eq!(
+            render_diagnostic(&diagnostic, "main.fy", source),
+            "error[E0003]: oops\n \
+             --> main.fy:1:6\n  \
+             |\n\
+             1 |     let    s = 1;\n  \
+             |            ^ \n"
+        );
+    }
+    #[test]
+    fn labels_on_the_same_line_share_it() {
+        let source = "let x = a + b;\n";
+        let diagnostic = Diagnostic::error("E0014", "mismatched types", span_of(source, "b"))
+            .with_label("cannot add")
+            .with_secondary(span_of(source, "a"), "first operand");
+
+        assert_eq!(
+            render_diagnostic(&diagnostic, "main.fy", source),
+            "error[E0014]: mismatched types\n \
+             --> main.fy:1:13\n  \
+             |\n\
+             1 | let x = a + b;\n  \
+             |         -   ^ cannot add\n  \
+             |         |\n  \
+             |         first operand\n"
+        );
+    }
+}